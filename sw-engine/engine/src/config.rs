@@ -34,25 +34,28 @@
 //!     name = "eth1"
 //!     device = "net_pcap0"
 //!     args = "rx_pcap=tcp4.pcap,tx_iface=lo"
-//!     cores = [0]
+//!     cores = [1]
 //!
 //! [[ports]]
 //!     name = "eth2"
 //!     device = "net_pcap1"
 //!     args = "rx_pcap=tcp6.pcap,tx_iface=lo"
-//!     cores = [0]
+//!     cores = [1]
 //! ```
 //!
 //! [`pktdump`]: https://github.com/capsule-rs/capsule/tree/master/examples/pktdump
 
-use crate::dpdk::CoreId;
+use crate::dockerlib::DEFAULT_ENDPOINT;
+use crate::dpdk::{CoreId, Ring, SocketId, RING_F_MP_MC, RING_F_SC_DEQ, RING_F_SP_ENQ};
+use crate::ensure;
 use crate::net::{Ipv4Cidr, Ipv6Cidr, MacAddr};
 use clap::{clap_app, crate_version};
-use failure::Fallible;
+use failure::{Fail, Fallible};
 use regex::Regex;
 use serde::{de, Deserialize, Deserializer};
 use std::fmt;
 use std::fs;
+use std::net::SocketAddr;
 use std::str::FromStr;
 use std::time::Duration;
 
@@ -67,6 +70,17 @@ impl<'de> Deserialize<'de> for CoreId {
     }
 }
 
+// make `SocketId` serde deserializable.
+impl<'de> Deserialize<'de> for SocketId {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let i = i32::deserialize(deserializer)?;
+        Ok(SocketId::new(i))
+    }
+}
+
 // make `MacAddr` serde deserializable.
 impl<'de> Deserialize<'de> for MacAddr {
     fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
@@ -129,6 +143,32 @@ where
     Ok(option)
 }
 
+/// Deserializes a ring capacity, rejecting sizes DPDK can not create.
+fn ring_capacity<'de, D>(deserializer: D) -> Result<usize, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let capacity = usize::deserialize(deserializer)?;
+    Ring::validate_capacity(capacity).map_err(de::Error::custom)?;
+    Ok(capacity)
+}
+
+/// Deserializes a control endpoint, only Unix sockets and TCP are supported.
+fn control_endpoint<'de, D>(deserializer: D) -> Result<String, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let endpoint = String::deserialize(deserializer)?;
+    if endpoint.starts_with("ipc://") || endpoint.starts_with("tcp://") {
+        Ok(endpoint)
+    } else {
+        Err(de::Error::custom(format!(
+            "unsupported control endpoint {}, expected ipc:// or tcp://",
+            endpoint
+        )))
+    }
+}
+
 /// Runtime configuration settings.
 #[derive(Clone, Deserialize)]
 #[serde(deny_unknown_fields)]
@@ -168,9 +208,21 @@ pub struct RuntimeConfig {
     #[serde(default)]
     pub mempool: MempoolConfig,
 
-    /// The ports to use for the application. Must have at least one.
+    /// The ports to use for the application. Must have at least one,
+    /// unless the process is secondary, which uses the ports of its primary.
+    /// Defaults to empty list.
+    #[serde(default)]
     pub ports: Vec<PortConfig>,
 
+    /// Switching engine settings.
+    #[serde(default)]
+    pub engine: EngineConfig,
+
+    /// The address to serve the metrics on, in the Prometheus text format,
+    /// e.g. `"127.0.0.1:9100"`. The metrics are not served if not set.
+    #[serde(default)]
+    pub metrics_address: Option<SocketAddr>,
+
     /// Additional DPDK [`parameters`] to pass on for EAL initialization. When
     /// set, the values are passed through as is without validation.
     ///
@@ -200,6 +252,26 @@ impl RuntimeConfig {
         cores
     }
 
+    /// Returns the cores the packet processing workers run on, in the
+    /// order the workers are numbered.
+    pub(crate) fn worker_cores(&self) -> Vec<CoreId> {
+        let mut cores = self
+            .ports
+            .iter()
+            .flat_map(|port| port.cores.iter().copied())
+            .collect::<Vec<_>>();
+
+        cores.sort();
+        cores.dedup();
+        cores
+    }
+
+    /// Returns the file prefix the processes of the group share memory
+    /// under, the `app_group` or else the `app_name`.
+    pub(crate) fn file_prefix(&self) -> &str {
+        self.app_group.as_ref().unwrap_or(&self.app_name)
+    }
+
     /// Extracts the EAL arguments from runtime settings.
     pub fn to_eal_args(&self) -> Vec<String> {
         let mut eal_args = vec![];
@@ -218,13 +290,13 @@ impl RuntimeConfig {
         eal_args.push(proc_type);
 
         // adds the mem file prefix
-        let prefix = self.app_group.as_ref().unwrap_or(&self.app_name);
         eal_args.push("--file-prefix".to_owned());
-        eal_args.push(prefix.clone());
+        eal_args.push(self.file_prefix().to_owned());
 
-        // adds all the ports
+        // adds all the ports, a secondary process uses the ports its
+        // primary already probed
         let pcie = Regex::new(r"^\d{4}:\d{2}:\d{2}\.\d$").unwrap();
-        self.ports.iter().for_each(|port| {
+        self.ports.iter().filter(|_| !self.secondary).for_each(|port| {
             if pcie.is_match(port.device.as_str()) {
                 eal_args.push("--pci-whitelist".to_owned());
                 eal_args.push(port.device.clone());
@@ -245,23 +317,8 @@ impl RuntimeConfig {
 
         // limits the EAL to only the master core. actual threads are
         // managed by the runtime not the EAL.
-        // changed by Deep
-        let mut cores: String = "".to_string();
-        self.ports.iter().for_each(|port| {
-            for core in port.cores.clone() {
-                let c = core.raw().to_string();
-                if !cores.contains(&c) {
-                    if cores.is_empty() {
-                        cores = c;
-                    } else {
-                        cores = format!("{}, {}", cores, c);
-                    }
-                }
-            }
-        });
         eal_args.push("-l".to_owned());
-        // eal_args.push(self.master_core.raw().to_string());
-        eal_args.push(cores);
+        eal_args.push(self.master_core.raw().to_string());
 
         // adds additional DPDK args
         if let Some(args) = &self.dpdk_args {
@@ -275,6 +332,76 @@ impl RuntimeConfig {
     pub(crate) fn num_knis(&self) -> usize {
         self.ports.iter().filter(|p| p.kni).count()
     }
+
+    /// Checks the settings that deserialization alone cannot catch.
+    ///
+    /// # Errors
+    ///
+    /// If a primary process has no port, if two ports share a name or a
+    /// device, if a port has no core assigned or is assigned the master
+    /// core, or if a core is beyond what DPDK supports, `ConfigError` is
+    /// returned.
+    pub fn validate(&self) -> Fallible<()> {
+        ensure!(self.secondary || !self.ports.is_empty(), ConfigError::NoPorts);
+
+        for core in self.all_cores() {
+            ensure!(
+                core.raw() < dpdk_ffi::RTE_MAX_LCORE as usize,
+                ConfigError::CoreOutOfRange(core.raw())
+            );
+        }
+
+        for (idx, port) in self.ports.iter().enumerate() {
+            let others = &self.ports[..idx];
+            ensure!(
+                others.iter().all(|other| other.name != port.name),
+                ConfigError::DuplicatePortName(port.name.clone())
+            );
+            ensure!(
+                others.iter().all(|other| other.device != port.device),
+                ConfigError::DuplicatePortDevice(port.device.clone())
+            );
+            ensure!(
+                !port.cores.is_empty(),
+                ConfigError::NoPortCores(port.name.clone())
+            );
+            ensure!(
+                !port.cores.contains(&self.master_core),
+                ConfigError::MasterCoreAssigned(port.name.clone())
+            );
+        }
+
+        Ok(())
+    }
+}
+
+/// Error indicating the configuration is not usable.
+#[derive(Debug, Fail)]
+pub enum ConfigError {
+    /// No port is configured.
+    #[fail(display = "At least one port is required.")]
+    NoPorts,
+
+    /// Two ports have the same name.
+    #[fail(display = "Port name {} is used more than once.", _0)]
+    DuplicatePortName(String),
+
+    /// Two ports use the same device.
+    #[fail(display = "Device {} is used by more than one port.", _0)]
+    DuplicatePortDevice(String),
+
+    /// A port has no core assigned.
+    #[fail(display = "Port {} is not assigned any cores.", _0)]
+    NoPortCores(String),
+
+    /// A port is assigned the master core, which is kept for the control
+    /// plane.
+    #[fail(display = "Port {} is assigned the master core.", _0)]
+    MasterCoreAssigned(String),
+
+    /// A core is beyond the lcores DPDK is built for.
+    #[fail(display = "Core {} is beyond RTE_MAX_LCORE.", _0)]
+    CoreOutOfRange(usize),
 }
 
 impl fmt::Debug for RuntimeConfig {
@@ -282,20 +409,21 @@ impl fmt::Debug for RuntimeConfig {
         let mut d = f.debug_struct("runtime");
         d.field("app_name", &self.app_name)
             .field("secondary", &self.secondary)
-            .field(
-                "app_group",
-                self.app_group.as_ref().unwrap_or(&self.app_name),
-            )
+            .field("app_group", &self.file_prefix())
             .field("master_core", &self.master_core)
             .field("cores", &self.cores)
             .field("mempool", &self.mempool)
-            .field("ports", &self.ports);
+            .field("ports", &self.ports)
+            .field("engine", &self.engine);
         if let Some(dpdk_args) = &self.dpdk_args {
             d.field("dpdk_args", dpdk_args);
         }
         if let Some(duration) = &self.duration {
             d.field("duration", duration);
         }
+        if let Some(metrics_address) = &self.metrics_address {
+            d.field("metrics_address", metrics_address);
+        }
         d.finish()
     }
 }
@@ -343,6 +471,187 @@ impl fmt::Debug for MempoolConfig {
     }
 }
 
+/// Switching engine configuration settings.
+#[derive(Clone, Deserialize)]
+pub struct EngineConfig {
+    /// How long, in seconds, a learnt MAC address stays in the forwarding
+    /// database without being seen again. Defaults to `300`.
+    #[serde(
+        default = "default_fdb_aging_time",
+        deserialize_with = "duration_from_secs"
+    )]
+    pub fdb_aging_time: Duration,
+
+    /// The default settings of the rings created for each client.
+    #[serde(default)]
+    pub rings: RingConfig,
+
+    /// How long, in seconds, a client may go without a heartbeat before
+    /// the engine considers it dead and frees its rings. Defaults to `10`.
+    #[serde(
+        default = "default_client_grace_period",
+        deserialize_with = "duration_from_secs"
+    )]
+    pub client_grace_period: Duration,
+
+    /// The endpoint the engine binds the control socket to, and the
+    /// containers connect to for registration. Either a Unix socket,
+    /// for example `ipc:///var/run/sw-engine/control.sock`, or TCP, for
+    /// example `tcp://127.0.0.1:5555`. Defaults to `DEFAULT_ENDPOINT`.
+    #[serde(
+        default = "default_control_endpoint",
+        deserialize_with = "control_endpoint"
+    )]
+    pub control_endpoint: String,
+
+    /// The file permissions of a Unix control socket. Only processes that
+    /// can write to the socket can register rings. Ignored for TCP.
+    /// Defaults to `0o660`.
+    #[serde(default = "default_control_socket_mode")]
+    pub control_socket_mode: u32,
+
+    /// How long, in seconds, the engine keeps forwarding the packets in
+    /// flight once asked to stop, before it frees whatever is left and
+    /// exits. Defaults to `5`.
+    #[serde(
+        default = "default_shutdown_timeout",
+        deserialize_with = "duration_from_secs"
+    )]
+    pub shutdown_timeout: Duration,
+}
+
+fn default_fdb_aging_time() -> Duration {
+    Duration::from_secs(300)
+}
+
+fn default_client_grace_period() -> Duration {
+    Duration::from_secs(10)
+}
+
+fn default_control_endpoint() -> String {
+    DEFAULT_ENDPOINT.to_owned()
+}
+
+fn default_control_socket_mode() -> u32 {
+    0o660
+}
+
+fn default_shutdown_timeout() -> Duration {
+    Duration::from_secs(5)
+}
+
+impl Default for EngineConfig {
+    fn default() -> Self {
+        EngineConfig {
+            fdb_aging_time: default_fdb_aging_time(),
+            rings: RingConfig::default(),
+            client_grace_period: default_client_grace_period(),
+            control_endpoint: default_control_endpoint(),
+            control_socket_mode: default_control_socket_mode(),
+            shutdown_timeout: default_shutdown_timeout(),
+        }
+    }
+}
+
+impl fmt::Debug for EngineConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("engine")
+            .field("fdb_aging_time", &self.fdb_aging_time)
+            .field("rings", &self.rings)
+            .field("client_grace_period", &self.client_grace_period)
+            .field("control_endpoint", &self.control_endpoint)
+            .field(
+                "control_socket_mode",
+                &format_args!("{:#o}", self.control_socket_mode),
+            )
+            .field("shutdown_timeout", &self.shutdown_timeout)
+            .finish()
+    }
+}
+
+/// Client ring configuration settings.
+///
+/// Each client gets a RX and a TX ring with these settings, unless it
+/// asks for others when it registers.
+#[derive(Clone, Deserialize)]
+pub struct RingConfig {
+    /// The number of slots in each ring. Must be a power of two, a ring
+    /// holds at most `capacity - 1` packets. Defaults to `1024`.
+    #[serde(default = "default_ring_capacity", deserialize_with = "ring_capacity")]
+    pub capacity: usize,
+
+    /// Whether a single thread enqueues onto each ring. The client is the
+    /// only writer of its TX ring. The engine workers take turns on a RX
+    /// ring, holding the client's channel while they enqueue. Defaults to
+    /// `true`.
+    #[serde(default = "default_single_producer")]
+    pub single_producer: bool,
+
+    /// Whether a single thread dequeues from each ring. Defaults to `true`.
+    #[serde(default = "default_single_consumer")]
+    pub single_consumer: bool,
+
+    /// The NUMA socket to allocate the rings on. Defaults to the socket of
+    /// the core running the engine.
+    #[serde(default)]
+    pub socket: Option<SocketId>,
+}
+
+fn default_ring_capacity() -> usize {
+    1024
+}
+
+fn default_single_producer() -> bool {
+    true
+}
+
+fn default_single_consumer() -> bool {
+    true
+}
+
+impl RingConfig {
+    /// Returns the `rte_ring_create` flags for the settings.
+    pub(crate) fn flags(&self) -> u32 {
+        let mut flags = RING_F_MP_MC;
+        if self.single_producer {
+            flags |= RING_F_SP_ENQ;
+        }
+        if self.single_consumer {
+            flags |= RING_F_SC_DEQ;
+        }
+        flags
+    }
+
+    /// Returns the socket to allocate the rings on.
+    pub(crate) fn socket_id(&self) -> SocketId {
+        self.socket.unwrap_or_else(SocketId::current)
+    }
+}
+
+impl Default for RingConfig {
+    fn default() -> Self {
+        RingConfig {
+            capacity: default_ring_capacity(),
+            single_producer: default_single_producer(),
+            single_consumer: default_single_consumer(),
+            socket: None,
+        }
+    }
+}
+
+impl fmt::Debug for RingConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut d = f.debug_struct("rings");
+        d.field("capacity", &self.capacity)
+            .field("single_producer", &self.single_producer)
+            .field("single_consumer", &self.single_consumer);
+        if let Some(socket) = &self.socket {
+            d.field("socket", socket);
+        }
+        d.finish()
+    }
+}
+
 /// Port configuration settings.
 #[derive(Clone, Deserialize)]
 pub struct PortConfig {
@@ -363,7 +672,8 @@ pub struct PortConfig {
     pub args: Option<String>,
 
     /// The cores assigned to the port for running the pipelines. The values
-    /// can overlap with the runtime cores.
+    /// can overlap with the runtime cores, but not with the master core,
+    /// which is kept for the control plane.
     pub cores: Vec<CoreId>,
 
     /// The receive queue capacity. Defaults to `128`.
@@ -469,6 +779,98 @@ mod tests {
         assert_eq!(false, config.ports[0].promiscuous);
         assert_eq!(default_multicast_mode(), config.ports[0].multicast);
         assert_eq!(false, config.ports[0].kni);
+        assert_eq!(default_fdb_aging_time(), config.engine.fdb_aging_time);
+        assert_eq!(default_ring_capacity(), config.engine.rings.capacity);
+        assert_eq!(
+            RING_F_SP_ENQ | RING_F_SC_DEQ,
+            config.engine.rings.flags()
+        );
+        assert_eq!(None, config.engine.rings.socket);
+        assert_eq!(
+            default_client_grace_period(),
+            config.engine.client_grace_period
+        );
+        assert_eq!(DEFAULT_ENDPOINT, config.engine.control_endpoint);
+        assert_eq!(0o660, config.engine.control_socket_mode);
+        assert_eq!(default_shutdown_timeout(), config.engine.shutdown_timeout);
+        assert_eq!(None, config.metrics_address);
+    }
+
+    #[test]
+    fn config_engine() {
+        const CONFIG: &str = r#"
+            app_name = "myapp"
+            master_core = 0
+            metrics_address = "127.0.0.1:9100"
+
+            [engine]
+                fdb_aging_time = 60
+                client_grace_period = 30
+                control_endpoint = "tcp://127.0.0.1:5555"
+                control_socket_mode = 0o600
+                shutdown_timeout = 2
+
+            [engine.rings]
+                capacity = 4096
+                single_consumer = false
+                socket = 1
+
+            [[ports]]
+                name = "eth0"
+                device = "0000:00:01.0"
+                cores = [2, 3]
+        "#;
+
+        let config: RuntimeConfig = toml::from_str(CONFIG).unwrap();
+
+        assert_eq!(Duration::from_secs(60), config.engine.fdb_aging_time);
+        assert_eq!(Duration::from_secs(30), config.engine.client_grace_period);
+        assert_eq!("tcp://127.0.0.1:5555", config.engine.control_endpoint);
+        assert_eq!(0o600, config.engine.control_socket_mode);
+        assert_eq!(Duration::from_secs(2), config.engine.shutdown_timeout);
+        assert_eq!(4096, config.engine.rings.capacity);
+        assert_eq!(RING_F_SP_ENQ, config.engine.rings.flags());
+        assert_eq!(Some(SocketId::new(1)), config.engine.rings.socket);
+        assert_eq!(
+            Some("127.0.0.1:9100".parse().unwrap()),
+            config.metrics_address
+        );
+    }
+
+    #[test]
+    fn config_ring_capacity() {
+        const CONFIG: &str = r#"
+            app_name = "myapp"
+            master_core = 0
+
+            [engine.rings]
+                capacity = 1000
+
+            [[ports]]
+                name = "eth0"
+                device = "0000:00:01.0"
+                cores = [2, 3]
+        "#;
+
+        assert!(toml::from_str::<RuntimeConfig>(CONFIG).is_err());
+    }
+
+    #[test]
+    fn config_control_endpoint() {
+        const CONFIG: &str = r#"
+            app_name = "myapp"
+            master_core = 0
+
+            [engine]
+                control_endpoint = "udp://127.0.0.1:5555"
+
+            [[ports]]
+                name = "eth0"
+                device = "0000:00:01.0"
+                cores = [2, 3]
+        "#;
+
+        assert!(toml::from_str::<RuntimeConfig>(CONFIG).is_err());
     }
 
     #[test]
@@ -496,12 +898,13 @@ mod tests {
                 name = "eth1"
                 device = "net_pcap0"
                 args = "rx=lo,tx=lo"
-                cores = [0, 4]
+                cores = [1, 4]
                 rxd = 32
                 txd = 32
         "#;
 
         let config: RuntimeConfig = toml::from_str(CONFIG).unwrap();
+        assert!(config.validate().is_ok());
 
         assert_eq!(
             &[
@@ -525,4 +928,108 @@ mod tests {
             config.to_eal_args().as_slice(),
         )
     }
+
+    #[test]
+    fn config_validate() {
+        const CONFIG: &str = r#"
+            app_name = "myapp"
+            master_core = 0
+
+            [[ports]]
+                name = "eth0"
+                device = "net_ring0"
+                cores = [1]
+
+            [[ports]]
+                name = "eth1"
+                device = "net_ring1"
+                cores = [2]
+        "#;
+
+        let config: RuntimeConfig = toml::from_str(CONFIG).unwrap();
+        assert!(config.validate().is_ok());
+
+        let mut no_ports = config.clone();
+        no_ports.ports.clear();
+        assert!(no_ports.validate().is_err());
+
+        let mut same_name = config.clone();
+        same_name.ports[1].name = "eth0".to_owned();
+        assert!(same_name.validate().is_err());
+
+        let mut same_device = config.clone();
+        same_device.ports[1].device = "net_ring0".to_owned();
+        assert!(same_device.validate().is_err());
+
+        let mut no_cores = config.clone();
+        no_cores.ports[0].cores.clear();
+        assert!(no_cores.validate().is_err());
+
+        let mut master_core = config.clone();
+        master_core.ports[0].cores.push(master_core.master_core);
+        assert!(master_core.validate().is_err());
+
+        let mut out_of_range = config.clone();
+        out_of_range.ports[1].cores = vec![CoreId::new(dpdk_ffi::RTE_MAX_LCORE as usize)];
+        assert!(out_of_range.validate().is_err());
+
+        let mut last_core = config;
+        last_core.cores = vec![CoreId::new(dpdk_ffi::RTE_MAX_LCORE as usize - 1)];
+        assert!(last_core.validate().is_ok());
+    }
+
+    #[test]
+    fn secondary_to_eal_args() {
+        const CONFIG: &str = r#"
+            app_name = "mysidecar"
+            secondary = true
+            app_group = "mygroup"
+            master_core = 3
+            dpdk_args = "-v"
+
+            [[ports]]
+                name = "eth0"
+                device = "0000:00:01.0"
+                cores = [2]
+        "#;
+
+        let config: RuntimeConfig = toml::from_str(CONFIG).unwrap();
+        assert!(config.validate().is_ok());
+
+        // the ports are probed by the primary only
+        assert_eq!(
+            &[
+                "mysidecar",
+                "--proc-type",
+                "secondary",
+                "--file-prefix",
+                "mygroup",
+                "--master-lcore",
+                "3",
+                "-l",
+                "3",
+                "-v"
+            ],
+            config.to_eal_args().as_slice(),
+        );
+    }
+
+    #[test]
+    fn secondary_without_ports() {
+        const CONFIG: &str = r#"
+            app_name = "mysidecar"
+            secondary = true
+            master_core = 3
+        "#;
+
+        let config: RuntimeConfig = toml::from_str(CONFIG).unwrap();
+        assert!(config.validate().is_ok());
+        assert_eq!("mysidecar", config.file_prefix());
+
+        let primary = RuntimeConfig {
+            secondary: false,
+            ..config
+        };
+        assert!(primary.validate().is_err());
+    }
 }
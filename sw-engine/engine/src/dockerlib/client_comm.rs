@@ -3,228 +3,244 @@
  * Created by Ratnadeep Bhattacharya
  */
 
-use crate::dpdk::{Channel, Ring, RingType};
-use lazy_static::lazy_static;
-use serde::{Deserialize, Serialize};
-// use serde_json::Result as sResult;
+use super::protocol::{ContainerInfo, Message, Request, Response, RingRequest};
+use failure::{format_err, Fail, Fallible};
 use std::{
-	cell::RefCell,
-	fmt, ptr, result,
-	sync::{Arc, RwLock},
+	cell::Cell,
+	time::{Duration, Instant},
 };
 use zmq;
 
-pub const SOCKET: &str = "tcp://localhost:5555";
-
-#[derive(Serialize, Deserialize)]
-pub enum ContMsgType {
-	PodStarting, // Register with the engine
-	PodReady,    // Let engine know that initialization is complete
-	PodStopping, // Pod is ending
-}
-
-impl fmt::Display for ContMsgType {
-	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-		match &self {
-			Self::PodStarting => write!(f, "0"),
-			Self::PodReady => write!(f, "1"),
-			Self::PodStopping => write!(f, "2"),
-		}
-	}
-}
-
-impl From<ContMsgType> for zmq::Message {
-	fn from(msg: ContMsgType) -> Self {
-		let mut m: u8 = match msg {
-			ContMsgType::PodStarting => 0,
-			ContMsgType::PodReady => 1,
-			ContMsgType::PodStopping => 2,
-		};
-		let mut data = zmq::Message::with_size(1);
-		unsafe { ptr::copy_nonoverlapping(&mut m as *mut _, data.as_mut_ptr(), 1) };
-		data
-	}
-}
-
-lazy_static! {
-	pub static ref CLIENT_ID: RwLock<u16> = RwLock::new(0);
+/// The control endpoint of the engine unless configured otherwise
+/// Only processes that can write to the socket file can register
+pub const DEFAULT_ENDPOINT: &str = "ipc:///var/run/sw-engine/control.sock";
+
+/// How long a request waits for the engine's answer
+pub const RESPONSE_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Client errors that are not socket failures or rejected requests
+#[derive(Debug, Fail)]
+pub enum ClientError {
+	/// The engine is stopping and answers no request anymore
+	#[fail(display = "Engine is shutting down")]
+	ShuttingDown,
+	/// The engine did not answer in time
+	#[fail(display = "No response from the engine within {:?}", _0)]
+	Timeout(Duration),
 }
 
-fn get_dockerclient_id() -> u16 {
-	let mut id = 0;
-	if let Ok(mut cl_id) = CLIENT_ID.write() {
-		id = *cl_id;
-		*cl_id += 1;
+impl ClientError {
+	/// Whether `err` is the engine announcing that it is stopping
+	pub fn is_shutting_down(err: &failure::Error) -> bool {
+		matches!(err.downcast_ref::<ClientError>(), Some(ClientError::ShuttingDown))
 	}
-	id
 }
 
 /// Represents a Docker client
 pub struct ContainerClient {
-	id: u16,                  // id of the client
-	socket: zmq::Socket,      // socket to send registration, start/stop msgs to the engine
-	channel: Option<Channel>, // channel to communicate with engine
+	id: Option<u16>,                 // id of the client, assigned by the engine
+	ring_request: RingRequest,       // settings of the rings to ask for, the engine's defaults where None
+	rings: Option<(String, String)>, // names of the RX and TX rings, assigned by the engine
+	info: ContainerInfo,             // addresses the container registers with
+	next_request: Cell<u64>,         // id of the next request, to match the answers
+	socket: zmq::Socket,             // socket to send registration, start/stop msgs to the engine
 }
 
-type Result<ContainerClient> = result::Result<ContainerClient, zmq::Error>;
-
 impl ContainerClient {
-	/// Return a new Client
-	pub fn new() -> Result<ContainerClient> {
+	/// Return a new Client for a container reachable at `info`
+	/// connected to the engine's control socket at `endpoint`
+	/// In case of failures it will attempt thrice to connect
+	pub fn new(info: ContainerInfo, endpoint: &str) -> zmq::Result<ContainerClient> {
 		let context = zmq::Context::new();
-		let id = get_dockerclient_id();
-		match context.socket(zmq::REQ) {
-			Ok(socket) => Ok(Self {
-				id,
-				socket,
-				channel: None,
-			}),
-			Err(err) => Err(err),
+		// DEALER rather than REQ, a lost answer must not wedge the socket
+		let socket = context.socket(zmq::DEALER)?;
+		// an engine that went away must not leave the client waiting forever
+		socket.set_rcvtimeo(RESPONSE_TIMEOUT.as_millis() as i32)?;
+		if let Err(ce) = socket.connect(endpoint) {
+			let mut connected = false;
+			// retry twice to connect before sending back the error
+			for _ in 0..2 {
+				if let Ok(_) = socket.connect(endpoint) {
+					connected = true;
+					break;
+				}
+			}
+			if !connected {
+				return Err(ce); // failed to connect to socket after three attempts
+			}
 		}
+		Ok(Self {
+			id: None,
+			ring_request: RingRequest::default(),
+			rings: None,
+			info,
+			next_request: Cell::new(0),
+			socket,
+		})
 	}
 
-	/// Get client ID
-	pub fn get_id(&self) -> u16 {
+	/// Get client ID, None until the engine registered the client
+	pub fn get_id(&self) -> Option<u16> {
 		self.id
 	}
 
+	/// Names of the RX and TX rings, None until the engine registered the client
+	pub fn get_rings(&self) -> Option<(&str, &str)> {
+		self.rings.as_ref().map(|(rx, tx)| (rx.as_str(), tx.as_str()))
+	}
+
+	/// Ask for rings of `capacity` slots when registering
+	/// Must be a power of two, the engine rejects the registration otherwise
+	pub fn set_ring_capacity(&mut self, capacity: usize) {
+		self.ring_request.capacity = Some(capacity);
+	}
+
+	/// Ask for rings with the settings of `rings` when registering
+	/// The engine rejects the registration if it can not create them
+	pub fn set_ring_request(&mut self, rings: RingRequest) {
+		self.ring_request = rings;
+	}
+
+	/// Client ID, or an error if the client is not registered yet
+	fn registered_id(&self) -> Fallible<u16> {
+		self.id.ok_or_else(|| format_err!("Client is not registered with the engine"))
+	}
+
 	/// Get client's channel to engine
 	pub fn get_socket(&self) -> &zmq::Socket {
 		&self.socket
 	}
 
-	/// Send message to engine about container state
-	/// In case of failures it will attempt thrice to connect
-	/// and thrice to send, in case of a successful connection
-	pub fn send(&self, msg: ContMsgType) -> zmq::Result<()> {
-		let cmsg = match msg {
-			ContMsgType::PodStarting => ContainerMsg::registration(self.get_id()).to_json(),
-			ContMsgType::PodReady => ContainerMsg::ready(self.get_id()).to_json(),
-			ContMsgType::PodStopping => ContainerMsg::terminating(self.get_id()).to_json(),
-		};
-
-		if let Err(ce) = self.get_socket().connect(SOCKET) {
-			let mut connected = false;
-			// retry twice to send the message before sending back the error
+	/// Send a request to the engine and wait for its response
+	/// In case of failures it will attempt thrice to send
+	///
+	/// Answers to earlier requests that timed out are skipped, the response
+	/// is the one that carries the id of this request
+	///
+	/// # Errors
+	///
+	/// Socket failures are returned as `zmq::Error`, a request rejected
+	/// by the engine as `ProtocolError`. If the engine does not answer
+	/// within `RESPONSE_TIMEOUT` or announces that it is stopping,
+	/// `ClientError` is returned
+	pub fn send(&self, req: Request) -> Fallible<Response> {
+		let request_id = self.next_request.get();
+		self.next_request.set(request_id.wrapping_add(1));
+		let cmsg = Message::with_request_id(Some(request_id), req).to_json()?;
+
+		// the empty delimiter frame makes the request look like it came from REQ
+		let frames = [&b""[..], cmsg.as_bytes()];
+		if let Err(se) = self.get_socket().send_multipart(&frames, 0) {
+			let mut sent = false;
 			for _ in 0..2 {
-				if let Ok(_) = self.get_socket().connect(SOCKET) {
-					connected = true;
+				if let Ok(_) = self.get_socket().send_multipart(&frames, 0) {
+					sent = true;
 					break;
 				}
 			}
-			if !connected {
-				return Err(ce); // failed to connect to socket after three attempts
+			if !sent {
+				return Err(se.into()); // failed to send message after three attempts
 			}
-		} else {
-			// connection successful
-			if let Err(se) = self.get_socket().send(&cmsg, 0) {
-				let mut sent = false;
-				for _ in 0..2 {
-					if let Ok(_) = self.get_socket().send(&cmsg, 0) {
-						sent = true;
-						break;
-					}
+		}
+
+		// every request is answered, the answer is the frame after the delimiter
+		let deadline = Instant::now() + RESPONSE_TIMEOUT;
+		loop {
+			let frames = match self.get_socket().recv_multipart(0) {
+				Ok(frames) => frames,
+				Err(zmq::Error::EAGAIN) => return Err(ClientError::Timeout(RESPONSE_TIMEOUT).into()),
+				Err(err) => return Err(err.into()),
+			};
+			match parse_response(frames)? {
+				(Some(id), response) if id == request_id => {
+					return match response {
+						Response::Error { error } => Err(error.into()),
+						response => Ok(response),
+					};
 				}
-				if !sent {
-					return Err(se); // failed to send message after three attempts
+				// the engine stopped before it got to the request
+				(None, Response::ShuttingDown) => return Err(ClientError::ShuttingDown.into()),
+				// the engine could not even read the request
+				(None, Response::Error { error }) => return Err(error.into()),
+				// a late answer to an earlier request
+				_ if Instant::now() >= deadline => {
+					return Err(ClientError::Timeout(RESPONSE_TIMEOUT).into());
 				}
+				_ => (),
 			}
 		}
-		Ok(()) // successfully sent
 	}
 
-	/// Register with the engine at startup
-	pub fn register(&self) -> zmq::Result<()> {
-		match self.send(ContMsgType::PodStarting) {
-			Err(e) => return Err(e),
-			Ok(_) => {
-				let mut msg = zmq::Message::new();
-				self.get_socket().recv(&mut msg, 0)
+	/// Whether the engine announced that it is stopping
+	/// Never blocks, must not be called while a request is in flight
+	pub fn engine_stopping(&self) -> Fallible<bool> {
+		// between requests, whatever is queued was sent unprompted
+		// or is a late answer to a request that timed out
+		loop {
+			match self.get_socket().recv_multipart(zmq::DONTWAIT) {
+				Ok(frames) => {
+					if parse_response(frames)? == (None, Response::ShuttingDown) {
+						return Ok(true);
+					}
+				}
+				Err(zmq::Error::EAGAIN) => return Ok(false),
+				Err(err) => return Err(err.into()),
 			}
 		}
 	}
 
-	/// Notify the engine that the container is ready
-	pub fn notify(&self) -> zmq::Result<()> {
-		self.send(ContMsgType::PodReady)
-	}
-
-	/// Notify the engine that the container is terminating
-	pub fn terminate(&self) -> zmq::Result<()> {
-		self.send(ContMsgType::PodStopping)
-	}
-
-	/// Once message from engine is successfully received
-	/// look up the tx and rx channels and set it up as
-	/// self.tx_channel and self.rx_channel
-	pub fn setup_channel(&mut self) {
-		if let Ok(_) = self.register() {
-			if let Some(t_ring) = Ring::from_ptr(self.id, RingType::TX, unsafe {
-				dpdk_ffi::rte_ring_lookup(format!("TX-{}", self.id).as_ptr() as *const _)
-			}) {
-				if let Some(r_ring) = Ring::from_ptr(self.id, RingType::RX, unsafe {
-					dpdk_ffi::rte_ring_lookup(format!("RX-{}", self.id).as_ptr() as *const _)
-				}) {
-					self.channel = Some(Channel {
-						tx_q: t_ring,
-						rx_q: r_ring,
-					})
-				}
+	/// Register with the engine at startup
+	/// The engine answers with the client id and the names of the rings
+	pub fn register(&mut self) -> Fallible<()> {
+		let req = Request::PodStarting {
+			container: self.info.clone(),
+			rings: self.ring_request.clone(),
+		};
+		match self.send(req)? {
+			Response::Registered { id, rx_ring, tx_ring } => {
+				self.id = Some(id);
+				self.rings = Some((rx_ring, tx_ring));
+				Ok(())
 			}
+			response => Err(format_err!("Unexpected response {:?} to registration", response)),
 		}
 	}
-}
-
-/// Represents a message to be sent to the engine
-///
-/// The message is sent in the following format for a container event
-///
-/// let msg = r#"
-///        {
-///            'id': 1,
-///            'msg': 1,
-///        }"#,
-///
-/// This corresponds to the message that container 1 is ready to run
-#[derive(Serialize, Deserialize)]
-pub struct ContainerMsg {
-	id: u16,
-	msg: ContMsgType,
-}
 
-impl ContainerMsg {
-	/// Returns a container registration struct
-	pub fn registration(id: u16) -> Self {
-		Self {
-			id,
-			msg: ContMsgType::PodStarting,
-		}
+	/// Notify the engine that the container is ready
+	pub fn notify(&self) -> Fallible<()> {
+		self.send(Request::PodReady {
+			id: self.registered_id()?,
+		})?;
+		Ok(())
 	}
 
-	/// Returns a container ready struct
-	pub fn ready(id: u16) -> Self {
-		Self {
-			id,
-			msg: ContMsgType::PodReady,
-		}
+	/// Tell the engine that the container is still alive
+	/// Must be called more often than the engine's client grace period,
+	/// otherwise the engine reaps the client and frees its rings
+	pub fn heartbeat(&self) -> Fallible<()> {
+		self.send(Request::Heartbeat {
+			id: self.registered_id()?,
+		})?;
+		Ok(())
 	}
 
-	/// Returns a container terminating struct
-	pub fn terminating(id: u16) -> Self {
-		Self {
-			id,
-			msg: ContMsgType::PodStopping,
-		}
+	/// Notify the engine that the container is terminating
+	/// The engine recycles the client id afterwards
+	pub fn terminate(&mut self) -> Fallible<()> {
+		self.send(Request::PodStopping {
+			id: self.registered_id()?,
+		})?;
+		self.rings = None;
+		self.id = None;
+		Ok(())
 	}
+}
 
-	pub fn to_json(&self) -> String {
-		format!(
-			r#"
-        {{
-            'id': {},
-            'msg': {},
-        }}"#,
-			self.id, self.msg
-		)
-	}
+/// The message is the frame after the routing envelope
+/// Returns the id of the request it answers along with the response
+fn parse_response(mut frames: Vec<Vec<u8>>) -> Fallible<(Option<u64>, Response)> {
+	let reply = frames.pop().ok_or_else(|| format_err!("Empty response"))?;
+	let reply = String::from_utf8(reply).map_err(|_| format_err!("Response is not UTF-8"))?;
+	let (request_id, response) = Message::<Response>::parse(&reply);
+	Ok((request_id, response?))
 }
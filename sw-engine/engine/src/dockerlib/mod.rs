@@ -4,5 +4,9 @@
  */
 
 pub mod client_comm;
+pub mod protocol;
+pub mod sidecar;
 
 pub use self::client_comm::*;
+pub use self::protocol::*;
+pub use self::sidecar::*;
@@ -0,0 +1,348 @@
+/*
+ * Created on Sun Oct 18 2026:14:05:37
+ * Created by Ratnadeep Bhattacharya
+ */
+
+//! The control protocol spoken between the containers and the engine
+//!
+//! Every message is a JSON object carrying the protocol version and a body,
+//! e.g. a container announcing that it is ready
+//!
+//! ```json
+//! { "version": 2, "body": { "type": "pod_ready", "id": 1 } }
+//! ```
+//!
+//! Every request is answered with a response, either `ok` or a typed error.
+//! A request may carry a `request_id`, which the engine echoes back on the
+//! response so that the client can tell it from a late answer to an earlier
+//! request
+//!
+//! ```json
+//! { "version": 2, "request_id": 7, "body": { "type": "ok" } }
+//! ```
+//!
+//! The engine assigns the client ids, a container learns its id and the
+//! names of its rings from the answer to `pod_starting`.
+//!
+//! The one message the engine sends unprompted is `shutting_down`, to every
+//! registered container when it stops.
+
+use crate::{dpdk::SocketId, net::MacAddr};
+use failure::{Fail, Fallible};
+use serde::{de::DeserializeOwned, Deserialize, Serialize, Serializer};
+use std::{net::Ipv4Addr, result};
+
+/// Version of the control protocol spoken by this build
+pub const PROTOCOL_VERSION: u16 = 2;
+
+// make `MacAddr` serde serializable, the other way round lives with the config
+impl Serialize for MacAddr {
+	fn serialize<S>(&self, serializer: S) -> result::Result<S::Ok, S::Error>
+	where
+		S: Serializer,
+	{
+		serializer.collect_str(self)
+	}
+}
+
+// make `SocketId` serde serializable, the other way round lives with the config
+impl Serialize for SocketId {
+	fn serialize<S>(&self, serializer: S) -> result::Result<S::Ok, S::Error>
+	where
+		S: Serializer,
+	{
+		serializer.serialize_i32(self.raw())
+	}
+}
+
+/// The addresses a container is reachable at
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ContainerInfo {
+	pub mac: MacAddr,
+	pub ip: Ipv4Addr,
+	/// VLAN the container is attached to, untagged if absent
+	#[serde(default)]
+	pub vlan: u16,
+}
+
+/// The ring settings a container asks for when registering
+/// Those left out are the engine's defaults
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct RingRequest {
+	/// Slots in each ring, a power of two
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub capacity: Option<usize>,
+	/// Whether the container enqueues onto its TX ring from a single thread
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub single_producer: Option<bool>,
+	/// Whether the container dequeues from its RX ring from a single thread
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub single_consumer: Option<bool>,
+	/// NUMA socket to allocate the rings on
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub socket: Option<SocketId>,
+}
+
+/// A request from a container to the engine
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Request {
+	/// Register with the engine, which assigns the client id
+	/// The rings get the engine's default settings unless others are asked for
+	PodStarting {
+		container: ContainerInfo,
+		#[serde(default)]
+		rings: RingRequest,
+	},
+	/// Let engine know that initialization is complete
+	PodReady { id: u16 },
+	/// Let engine know that the container is alive
+	/// A client that stays silent for longer than the engine's grace period is reaped
+	Heartbeat { id: u16 },
+	/// Pod is ending
+	PodStopping { id: u16 },
+}
+
+impl Request {
+	/// The client the request acts on, None for a registration
+	pub fn client_id(&self) -> Option<u16> {
+		match self {
+			Request::PodStarting { .. } => None,
+			Request::PodReady { id } | Request::Heartbeat { id } | Request::PodStopping { id } => Some(*id),
+		}
+	}
+}
+
+/// The engine's answer to a `Request`
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Response {
+	/// The request was applied
+	Ok,
+	/// The container was registered as client `id`
+	/// and can look up its rings by name
+	Registered {
+		id: u16,
+		rx_ring: String,
+		tx_ring: String,
+	},
+	/// The request was rejected
+	Error { error: ProtocolError },
+	/// The engine is stopping, sent unprompted to every registered client
+	/// The rings go away with the engine, the client must stop using them
+	ShuttingDown,
+}
+
+/// Reasons for which the engine rejects a request
+#[derive(Clone, Debug, Fail, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "kind", content = "detail", rename_all = "snake_case")]
+pub enum ProtocolError {
+	/// The peer speaks another version of the protocol
+	#[fail(display = "Unsupported protocol version {}, expected {}", got, expected)]
+	UnsupportedVersion { expected: u16, got: u16 },
+	/// The message could not be parsed
+	#[fail(display = "Malformed message: {}", _0)]
+	Malformed(String),
+	/// Every client id is in use
+	#[fail(display = "No client id left")]
+	NoClientId,
+	/// The ring capacity asked for at registration can not be created
+	#[fail(display = "Invalid ring capacity {}", _0)]
+	InvalidRingCapacity(usize),
+	/// The NUMA socket asked for at registration does not exist
+	#[fail(display = "No NUMA {:?} to allocate the rings on", _0)]
+	UnknownSocket(SocketId),
+	/// The request is for a container that never registered
+	#[fail(display = "Client {} is not registered", _0)]
+	UnknownClient(u16),
+	/// Another client already owns the MAC address on that VLAN
+	#[fail(display = "{} on VLAN {} is in use by another client", _0, _1)]
+	AddressInUse(MacAddr, u16),
+	/// The engine failed to apply the request
+	#[fail(display = "Engine failed: {}", _0)]
+	Internal(String),
+}
+
+/// A message on the control socket, tagged with the protocol version
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Message<T> {
+	pub version: u16,
+	/// Set on a request and echoed back on its response
+	/// The notices the engine sends unprompted have none
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub request_id: Option<u64>,
+	pub body: T,
+}
+
+impl<T: Serialize> Message<T> {
+	/// Wrap `body` in a message of the current protocol version
+	pub fn new(body: T) -> Self {
+		Self::with_request_id(None, body)
+	}
+
+	/// Wrap `body` in a message asking or answering request `request_id`
+	pub fn with_request_id(request_id: Option<u64>, body: T) -> Self {
+		Self {
+			version: PROTOCOL_VERSION,
+			request_id,
+			body,
+		}
+	}
+
+	/// Serialize the message to send it on the control socket
+	pub fn to_json(&self) -> Fallible<String> {
+		Ok(serde_json::to_string(self)?)
+	}
+}
+
+impl<T: DeserializeOwned> Message<T> {
+	/// Parse a message received on the control socket and return its body
+	///
+	/// The version is checked before the body is parsed, so that a peer
+	/// speaking another version gets a version error rather than a parse error
+	pub fn from_json(data: &str) -> result::Result<T, ProtocolError> {
+		Self::parse(data).1
+	}
+
+	/// Parse a message received on the control socket
+	///
+	/// Returns the request id, as long as the message is JSON that carries
+	/// one, along with the body, so that even a rejected request is answered
+	/// with its id
+	pub fn parse(data: &str) -> (Option<u64>, result::Result<T, ProtocolError>) {
+		let msg: Message<serde_json::Value> = match serde_json::from_str(data) {
+			Ok(msg) => msg,
+			Err(err) => return (None, Err(ProtocolError::Malformed(err.to_string()))),
+		};
+		if msg.version != PROTOCOL_VERSION {
+			let error = ProtocolError::UnsupportedVersion {
+				expected: PROTOCOL_VERSION,
+				got: msg.version,
+			};
+			return (msg.request_id, Err(error));
+		}
+		let body = serde_json::from_value(msg.body).map_err(|err| ProtocolError::Malformed(err.to_string()));
+		(msg.request_id, body)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn request_roundtrip() {
+		let req = Request::PodStarting {
+			container: ContainerInfo {
+				mac: MacAddr::new(0x02, 0, 0, 0, 0, 0x01),
+				ip: Ipv4Addr::new(10, 0, 0, 1),
+				vlan: 10,
+			},
+			rings: RingRequest {
+				capacity: Some(256),
+				single_consumer: Some(false),
+				socket: Some(SocketId::new(1)),
+				..RingRequest::default()
+			},
+		};
+		let data = Message::new(req.clone()).to_json().unwrap();
+		assert_eq!(Ok(req), Message::<Request>::from_json(&data));
+	}
+
+	#[test]
+	fn response_roundtrip() {
+		let resp = Response::Error {
+			error: ProtocolError::UnknownClient(3),
+		};
+		let data = Message::new(resp.clone()).to_json().unwrap();
+		assert_eq!(Ok(resp), Message::<Response>::from_json(&data));
+
+		let resp = Response::Registered {
+			id: 3,
+			rx_ring: "RX-3".into(),
+			tx_ring: "TX-3".into(),
+		};
+		let data = Message::new(resp.clone()).to_json().unwrap();
+		assert_eq!(Ok(resp), Message::<Response>::from_json(&data));
+
+		let data = Message::new(Response::ShuttingDown).to_json().unwrap();
+		assert_eq!(r#"{"version":2,"body":{"type":"shutting_down"}}"#, data);
+		assert_eq!(Ok(Response::ShuttingDown), Message::<Response>::from_json(&data));
+	}
+
+	#[test]
+	fn parse_request() {
+		let data = r#"{
+			"version": 2,
+			"body": {
+				"type": "pod_starting",
+				"container": { "mac": "02:00:00:00:00:01", "ip": "10.0.0.1" }
+			}
+		}"#;
+		match Message::<Request>::from_json(data) {
+			Ok(Request::PodStarting { container, rings }) => {
+				assert_eq!(Ipv4Addr::new(10, 0, 0, 1), container.ip);
+				assert_eq!(0, container.vlan);
+				assert_eq!(RingRequest::default(), rings);
+			}
+			other => panic!("unexpected {:?}", other),
+		}
+	}
+
+	#[test]
+	fn reject_other_version() {
+		let data = r#"{ "version": 1, "body": { "type": "pod_ready", "id": 1 } }"#;
+		assert_eq!(
+			Err(ProtocolError::UnsupportedVersion {
+				expected: PROTOCOL_VERSION,
+				got: 1
+			}),
+			Message::<Request>::from_json(data)
+		);
+	}
+
+	#[test]
+	fn reject_malformed() {
+		// the pseudo-JSON the first version of the client used to send
+		let data = "{ 'id': 1, 'msg': 1, }";
+		match Message::<Request>::from_json(data) {
+			Err(ProtocolError::Malformed(_)) => (),
+			other => panic!("unexpected {:?}", other),
+		}
+
+		let data = r#"{ "version": 2, "body": { "type": "pod_exploding", "id": 1 } }"#;
+		match Message::<Request>::from_json(data) {
+			Err(ProtocolError::Malformed(_)) => (),
+			other => panic!("unexpected {:?}", other),
+		}
+	}
+
+	#[test]
+	fn echo_request_id() {
+		let data = Message::with_request_id(Some(7), Request::Heartbeat { id: 1 }).to_json().unwrap();
+		assert_eq!(
+			(Some(7), Ok(Request::Heartbeat { id: 1 })),
+			Message::<Request>::parse(&data)
+		);
+
+		// the notices carry no id
+		let data = Message::new(Response::ShuttingDown).to_json().unwrap();
+		assert_eq!((None, Ok(Response::ShuttingDown)), Message::<Response>::parse(&data));
+
+		// a rejected request keeps its id
+		let data = r#"{ "version": 1, "request_id": 8, "body": { "type": "pod_ready", "id": 1 } }"#;
+		match Message::<Request>::parse(data) {
+			(Some(8), Err(ProtocolError::UnsupportedVersion { .. })) => (),
+			other => panic!("unexpected {:?}", other),
+		}
+		let data = r#"{ "version": 2, "request_id": 9, "body": { "type": "pod_exploding" } }"#;
+		match Message::<Request>::parse(data) {
+			(Some(9), Err(ProtocolError::Malformed(_))) => (),
+			other => panic!("unexpected {:?}", other),
+		}
+		match Message::<Request>::parse("{ 'request_id': 10 }") {
+			(None, Err(ProtocolError::Malformed(_))) => (),
+			other => panic!("unexpected {:?}", other),
+		}
+	}
+}
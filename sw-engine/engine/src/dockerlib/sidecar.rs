@@ -0,0 +1,228 @@
+/*
+ * Created on Sun Oct 18 2026:17:36:54
+ * Created by Ratnadeep Bhattacharya
+ */
+
+//! The sidecar's end of the data plane
+//!
+//! A container sidecar attaches to the engine's shared memory, connects to
+//! the engine, gets a pair of rings and then exchanges packets with the
+//! engine in bursts
+//!
+//! The engine reaps a client it has not heard from for its grace period.
+//! `engine_stopping` keeps the sidecar alive, it sends a heartbeat every
+//! `HEARTBEAT_INTERVAL`. A sidecar that does not call it that often must
+//! call `heartbeat` itself
+//!
+//! ```ignore
+//! dockerlib::bootstrap(&config)?;
+//! let mut sidecar = dockerlib::connect(info, &config.engine.control_endpoint)?;
+//! while !sidecar.engine_stopping() {
+//!     let pkts = sidecar.recv_burst(32);
+//!     // ... process the packets
+//!     let unsent = sidecar.send_burst(pkts);
+//! }
+//! sidecar.close()?;
+//! ```
+
+use super::{
+	client_comm::{ClientError, ContainerClient},
+	protocol::ContainerInfo,
+};
+use crate::{
+	config::RuntimeConfig,
+	dpdk::{self, Channel, Mbuf, Ring, SharedMempool, SocketId},
+};
+use failure::{Fail, Fallible};
+use std::{
+	cell::Cell,
+	result,
+	time::{Duration, Instant},
+};
+
+/// How often `engine_stopping` tells the engine that the container is alive
+/// Well within the engine's default grace period of 10 seconds
+pub const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(1);
+
+/// The step of `bootstrap` or `connect` that failed
+#[derive(Debug, Fail)]
+pub enum SidecarError {
+	/// The configuration is not the one of a secondary process
+	#[fail(display = "Invalid sidecar configuration: {}", _0)]
+	Config(String),
+
+	/// No engine shares memory under the configured file prefix
+	#[fail(display = "No engine running with file prefix {}, start the engine first", _0)]
+	PrimaryNotRunning(String),
+
+	/// The EAL could not attach to the engine's memory
+	#[fail(display = "Failed to initialize the EAL as a secondary process: {}", _0)]
+	Eal(String),
+
+	/// The control socket could not be set up
+	#[fail(display = "Failed to connect to the engine at {}: {}", _0, _1)]
+	Connect(String, String),
+
+	/// The engine did not register the container
+	#[fail(display = "Failed to register with the engine: {}", _0)]
+	Register(String),
+
+	/// A ring the engine created for the container could not be found
+	#[fail(display = "Ring {} not found", _0)]
+	RingLookup(String),
+
+	/// The engine's mempool could not be found
+	#[fail(display = "Mempool lookup failed: {}", _0)]
+	MempoolLookup(String),
+}
+
+/// A connected sidecar
+pub struct Sidecar {
+	client: ContainerClient,
+	channel: Channel,
+	mempool: SharedMempool, // the engine's mempool the packets are allocated from
+	stopping: Cell<bool>, // the engine announced that it is stopping
+	last_heartbeat: Cell<Instant>, // when the engine last heard from the container
+}
+
+/// Attach the process to the engine's shared memory
+///
+/// Initializes the EAL as a secondary process of the engine and looks up
+/// the engine's mempool for the calling thread. `config` must be marked
+/// `secondary` and share the `app_group` of the engine. Must be called once,
+/// before `connect`.
+pub fn bootstrap(config: &RuntimeConfig) -> result::Result<(), SidecarError> {
+	if !config.secondary {
+		return Err(SidecarError::Config("the sidecar must be a secondary process".into()));
+	}
+	config.validate().map_err(|err| SidecarError::Config(err.to_string()))?;
+	// the EAL of a secondary process fails in obscure ways without a primary
+	if !dpdk::primary_proc_alive(config.file_prefix()) {
+		return Err(SidecarError::PrimaryNotRunning(config.file_prefix().to_owned()));
+	}
+	dpdk::eal_init(config.to_eal_args()).map_err(|err| SidecarError::Eal(err.to_string()))?;
+	dpdk::attach_mempool(SocketId::current())
+		.map_err(|err| SidecarError::MempoolLookup(err.to_string()))
+}
+
+/// Connect to the engine at `endpoint` as a container reachable at `info`
+///
+/// Registers with the engine, looks up the rings it created and the mempool
+/// packets are allocated from, then tells the engine the container is ready.
+/// The process must be bootstrapped beforehand.
+pub fn connect(info: ContainerInfo, endpoint: &str) -> result::Result<Sidecar, SidecarError> {
+	let mut client = ContainerClient::new(info, endpoint)
+		.map_err(|err| SidecarError::Connect(endpoint.to_owned(), err.to_string()))?;
+	client
+		.register()
+		.map_err(|err| SidecarError::Register(err.to_string()))?;
+
+	match open_channel(&client) {
+		Ok((channel, mempool)) => Ok(Sidecar {
+			client,
+			channel,
+			mempool,
+			stopping: Cell::new(false),
+			last_heartbeat: Cell::new(Instant::now()),
+		}),
+		Err(err) => {
+			// do not leave the rings behind on the engine
+			client.terminate().ok();
+			Err(err)
+		}
+	}
+}
+
+/// Look up the client's rings and mempool, and report the client ready
+fn open_channel(client: &ContainerClient) -> result::Result<(Channel, SharedMempool), SidecarError> {
+	let (id, (rx_name, tx_name)) = match (client.get_id(), client.get_rings()) {
+		(Some(id), Some(rings)) => (id, rings),
+		_ => return Err(SidecarError::Register("no client id assigned".into())),
+	};
+	let rx_q = lookup_ring(rx_name)?;
+	let tx_q = lookup_ring(tx_name)?;
+	let mempool = SharedMempool::lookup(SocketId::current())
+		.map_err(|err| SidecarError::MempoolLookup(err.to_string()))?;
+	client
+		.notify()
+		.map_err(|err| SidecarError::Register(err.to_string()))?;
+	// the engine delivers on the RX ring and reads the TX ring
+	Ok((Channel::new(id, tx_q, rx_q), mempool))
+}
+
+// the rings belong to the engine, the client only borrows them
+fn lookup_ring(name: &str) -> result::Result<Ring, SidecarError> {
+	Ring::lookup(name).map_err(|_| SidecarError::RingLookup(name.to_owned()))
+}
+
+impl Sidecar {
+	/// Client id the engine assigned to the container
+	/// None if the container is not registered
+	pub fn id(&self) -> Option<u16> {
+		self.client.get_id()
+	}
+
+	/// Receive up to `max` packets from the engine
+	pub fn recv_burst(&mut self, max: usize) -> Vec<Mbuf> {
+		self.channel.receive_burst(max)
+	}
+
+	/// Send packets to the engine
+	/// Returns the packets that did not fit on the ring, to retry or drop
+	pub fn send_burst(&mut self, pkts: Vec<Mbuf>) -> Vec<Mbuf> {
+		self.channel.send_burst(pkts)
+	}
+
+	/// Allocate a packet from the engine's mempool
+	pub fn alloc_mbuf(&self) -> Fallible<Mbuf> {
+		Mbuf::new_in(self.mempool)
+	}
+
+	/// Tell the engine that the container is still alive
+	pub fn heartbeat(&self) -> Fallible<()> {
+		let res = self.client.heartbeat();
+		if res.is_ok() {
+			self.last_heartbeat.set(Instant::now());
+		}
+		self.watch(res)
+	}
+
+	/// Whether the engine announced that it is stopping
+	/// The rings go away with the engine, stop sending and close the sidecar
+	///
+	/// Sends a heartbeat once `HEARTBEAT_INTERVAL` passed since the last one
+	pub fn engine_stopping(&self) -> bool {
+		if !self.stopping.get() && self.client.engine_stopping().unwrap_or(false) {
+			self.stopping.set(true);
+		}
+		if !self.stopping.get() && self.last_heartbeat.get().elapsed() >= HEARTBEAT_INTERVAL {
+			// a missed heartbeat is retried on the next call
+			let _ = self.heartbeat();
+		}
+		self.stopping.get()
+	}
+
+	/// Unregister from the engine
+	/// The engine frees the rings and whatever packets are left on them
+	pub fn close(mut self) -> Fallible<()> {
+		// a stopping engine answers nothing anymore, and frees the rings itself
+		if self.stopping.get() {
+			return Ok(());
+		}
+		match self.client.terminate() {
+			Err(ref err) if ClientError::is_shutting_down(err) => Ok(()),
+			res => res,
+		}
+	}
+
+	/// Remember if the engine announced that it is stopping in the answer
+	/// to a request, it is not queued for `engine_stopping` anymore
+	fn watch<T>(&self, res: Fallible<T>) -> Fallible<T> {
+		if let Err(ref err) = res {
+			if ClientError::is_shutting_down(err) {
+				self.stopping.set(true);
+			}
+		}
+		res
+	}
+}
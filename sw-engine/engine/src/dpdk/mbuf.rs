@@ -5,7 +5,7 @@
 
 use super::MEMPOOL;
 use crate::{
-	dpdk::{DpdkError, MempoolError},
+	dpdk::{DpdkError, MempoolError, SharedMempool},
 	ensure,
 	ffi::ToResult,
 	trace,
@@ -103,8 +103,9 @@ enum MbufInner {
 	/// Should be freed when goes out of scope
 	/// Pointer returned to DPDK
 	Original(NonNull<dpdk_ffi::rte_mbuf>),
-	/// A clone version of the message buffer
-	/// should not be freed when it goes out of scope
+	/// An indirect message buffer attached to the data of another one
+	/// Freeing it detaches it and drops its reference on the data,
+	/// which goes back to DPDK with the last reference
 	Clone(NonNull<dpdk_ffi::rte_mbuf>),
 }
 
@@ -128,7 +129,18 @@ impl Mbuf {
 	/// Creates a new message buffer
 	#[inline]
 	pub fn new() -> Fallible<Self> {
-		let mempool = MEMPOOL.with(|tls| tls.get());
+		Mbuf::alloc(MEMPOOL.with(|tls| tls.get()))
+	}
+
+	/// Creates a new message buffer from `mempool`
+	/// rather than the mempool of the current thread
+	#[inline]
+	pub fn new_in(mempool: SharedMempool) -> Fallible<Self> {
+		Mbuf::alloc(mempool.as_ptr())
+	}
+
+	#[inline]
+	fn alloc(mempool: *mut dpdk_ffi::rte_mempool) -> Fallible<Self> {
 		let raw = unsafe {
 			dpdk_ffi::_rte_pktmbuf_alloc(mempool).to_result(|_| MempoolError::Exhausted)?
 		};
@@ -155,6 +167,24 @@ impl Mbuf {
 		}
 	}
 
+	/// Creates a zero-copy clone of the message buffer
+	///
+	/// The clone is an indirect mbuf from the same mempool attached to the
+	/// packet data, whose reference count is incremented instead of copying it.
+	/// The clone has its own offsets and lengths, but the data is shared, so
+	/// neither this buffer nor the clone should write to it afterwards.
+	#[inline]
+	pub fn clone_shallow(&self) -> Fallible<Self> {
+		let raw = unsafe {
+			dpdk_ffi::rte_pktmbuf_clone(self.inner.ptr().as_ptr(), self.raw().pool)
+				.to_result(|_| MempoolError::Exhausted)?
+		};
+
+		Ok(Mbuf {
+			inner: MbufInner::Clone(raw),
+		})
+	}
+
 	/// Returns the raw struct needed for FFI calls
 	#[inline]
 	pub fn raw(&self) -> &dpdk_ffi::rte_mbuf {
@@ -408,13 +438,18 @@ impl fmt::Debug for Mbuf {
 impl Drop for Mbuf {
 	fn drop(&mut self) {
 		match self.inner {
-			MbufInner::Original(_) => {
-				trace!("freeing mbuf@{:p}.", self.raw().buf_addr);
-				unsafe {
-					dpdk_ffi::_rte_pktmbuf_free(self.raw_mut());
-				}
-			}
-			MbufInner::Clone(_) => (),
+			MbufInner::Original(_) => trace!("freeing mbuf@{:p}.", self.raw().buf_addr),
+			MbufInner::Clone(_) => trace!("freeing clone of mbuf@{:p}.", self.raw().buf_addr),
+		}
+		// rte_pktmbuf_free only decrements the reference count of shared data
+		// and detaches clones before returning them to their mempool
+		unsafe {
+			dpdk_ffi::_rte_pktmbuf_free(self.raw_mut());
 		}
 	}
 }
+
+// a message buffer is owned by one handle at a time and DPDK mempools can
+// take their buffers back from any core, the workers hand packets to each
+// other for the ports they do not transmit on
+unsafe impl Send for Mbuf {}
@@ -13,6 +13,7 @@ use failure::{Fail, Fallible};
 use std::{
 	cell::Cell,
 	collections::HashMap,
+	ffi::c_void,
 	fmt,
 	os::raw,
 	ptr::{self, NonNull},
@@ -112,6 +113,89 @@ thread_local! {
 	pub static MEMPOOL: Cell<*mut dpdk_ffi::rte_mempool> = Cell::new(ptr::null_mut());
 }
 
+/// Name of the mempool on `socket_id`
+/// The primary process creates its mempools under these names so that
+/// secondary processes can look them up
+pub fn mempool_name(socket_id: SocketId) -> String {
+	format!("mempool{}", socket_id.raw())
+}
+
+/// A mempool the primary process created, looked up by name
+///
+/// The mempool belongs to the primary, dropping the handle does not free it
+#[derive(Clone, Copy, Debug)]
+pub struct SharedMempool {
+	raw: NonNull<dpdk_ffi::rte_mempool>,
+}
+
+impl SharedMempool {
+	/// Look up the mempool the primary process created on `socket_id`
+	///
+	/// # Errors
+	///
+	/// If there is no such mempool, `MempoolError::NotFound` is returned
+	pub fn lookup(socket_id: SocketId) -> Fallible<Self> {
+		let name = mempool_name(socket_id);
+		let raw = unsafe {
+			dpdk_ffi::rte_mempool_lookup(name.to_cstring().as_ptr())
+				.to_result(|_| MempoolError::NotFound(socket_id))?
+		};
+		Ok(Self { raw })
+	}
+
+	/// Returns the raw pointer required for FFI calls
+	#[inline]
+	pub(crate) fn as_ptr(self) -> *mut dpdk_ffi::rte_mempool {
+		self.raw.as_ptr()
+	}
+}
+
+// the mempool lives in shared memory and allocates from any thread
+unsafe impl Send for SharedMempool {}
+unsafe impl Sync for SharedMempool {}
+
+/// Look up the mempool the primary process created on `socket_id` and make
+/// it the one `Mbuf`s are allocated from on the current thread
+///
+/// # Errors
+///
+/// If there is no such mempool, `MempoolError::NotFound` is returned
+pub fn attach_mempool(socket_id: SocketId) -> Fallible<()> {
+	let mempool = SharedMempool::lookup(socket_id)?;
+	MEMPOOL.with(|tls| tls.set(mempool.as_ptr()));
+	Ok(())
+}
+
+/// Occupancy of a mempool
+#[derive(Clone, Debug)]
+pub struct MempoolStats {
+	pub name: String,
+	/// Number of mbufs allocated from the mempool
+	pub used: usize,
+	/// Number of mbufs available for allocation
+	pub free: usize,
+}
+
+/// Occupancy of every mempool of the process
+/// A secondary process sees the mempools of its primary too
+pub fn mempool_stats() -> Vec<MempoolStats> {
+	unsafe extern "C" fn visit(mp: *mut dpdk_ffi::rte_mempool, arg: *mut c_void) {
+		let stats = &mut *(arg as *mut Vec<MempoolStats>);
+		let pool = &*mp;
+		stats.push(MempoolStats {
+			name: pool.name[..].as_str().to_owned(),
+			used: dpdk_ffi::rte_mempool_in_use_count(mp) as usize,
+			free: dpdk_ffi::rte_mempool_avail_count(mp) as usize,
+		});
+	}
+
+	let mut stats: Vec<MempoolStats> = Vec::new();
+	unsafe {
+		dpdk_ffi::rte_mempool_walk(Some(visit), &mut stats as *mut _ as *mut c_void);
+	}
+	stats
+}
+
 /// Error indicating the `Mempool` is found or is exhausted
 #[derive(Debug, Fail)]
 pub enum MempoolError {
@@ -9,13 +9,30 @@ use crate::{
 	dpdk::{DpdkError, Mbuf},
 	ffi::{AsStr, ToCString, ToResult},
 	info,
+	metrics::{self, Counters, Direction, DropCause, Totals},
 };
 use chashmap::CHashMap;
 use dpdk_ffi;
-use failure::{format_err, Fallible};
-use std::{collections::HashMap, ffi::c_void, fmt, os::raw, ptr::NonNull, sync::Arc};
+use failure::{format_err, Fail, Fallible};
+use std::{
+	collections::HashMap,
+	ffi::c_void,
+	fmt,
+	os::raw,
+	ptr::{self, NonNull},
+	sync::Arc,
+};
+
+/// Ring flags, from rte_ring.h
+/// The default is multi-producer/multi-consumer
+pub const RING_F_MP_MC: u32 = 0x0000;
+/// Only one thread enqueues onto the ring
+pub const RING_F_SP_ENQ: u32 = 0x0001;
+/// Only one thread dequeues from the ring
+pub const RING_F_SC_DEQ: u32 = 0x0002;
 
-const NO_FLAGS: u8 = 0;
+/// Number of packets freed at a time when draining a ring
+const DRAIN_BURST: usize = 32;
 
 /// The RingType is whether message is being sent from engine to container or from contianer to engine
 pub enum RingType {
@@ -23,139 +40,247 @@ pub enum RingType {
 	TX,
 }
 
-/// A ring is intended to communicate between two DPDK processes by sending/receiving `Mbuf`.
-/// For best performance, each socket should have a dedicated `Mempool`.
-pub struct Ring {
-	client_id: u16,
-	rtype: RingType,
-	raw: NonNull<dpdk_ffi::rte_ring>,
+impl RingType {
+	/// Name of the ring of this type for client `client_id`
+	#[inline]
+	pub fn ring_name(&self, client_id: u16) -> String {
+		match self {
+			RingType::RX => format!("RX-{}", client_id),
+			RingType::TX => format!("TX-{}", client_id),
+		}
+	}
 }
 
-// allows to create a ring before a client id is available
-// most likely will not be required
-impl Default for Ring {
-	fn default() -> Self {
-		Self {
-			client_id: 0,
-			rtype: RingType::TX,
-			raw: NonNull::dangling(),
+/// Error indicating a `Ring` operation failed
+#[derive(Debug, Fail)]
+pub enum RingError {
+	/// The ring has no room left for the packet
+	#[fail(display = "Ring {} is full.", _0)]
+	Full(String),
+
+	/// There is no packet on the ring
+	#[fail(display = "Ring {} is empty.", _0)]
+	Empty(String),
+
+	/// DPDK rings are sized to a power of two
+	#[fail(display = "Ring capacity {} is not a power of two.", _0)]
+	InvalidCapacity(usize),
+
+	/// No ring was created under the name
+	#[fail(display = "Ring {} not found.", _0)]
+	NotFound(String),
+}
+
+/// Rings are shared between processes, only the one that created a ring frees it
+enum RingInner {
+	/// Ring created by this process
+	/// Should be freed when goes out of scope
+	Owned(NonNull<dpdk_ffi::rte_ring>),
+	/// Ring looked up from the process that created it
+	/// Left alone when goes out of scope
+	Borrowed(NonNull<dpdk_ffi::rte_ring>),
+}
+
+impl RingInner {
+	fn ptr(&self) -> &NonNull<dpdk_ffi::rte_ring> {
+		match self {
+			RingInner::Owned(raw) => raw,
+			RingInner::Borrowed(raw) => raw,
+		}
+	}
+
+	fn ptr_mut(&mut self) -> &mut NonNull<dpdk_ffi::rte_ring> {
+		match self {
+			RingInner::Owned(ref mut raw) => raw,
+			RingInner::Borrowed(ref mut raw) => raw,
 		}
 	}
 }
 
+/// A ring is intended to communicate between two DPDK processes by sending/receiving `Mbuf`.
+/// For best performance, each socket should have a dedicated `Mempool`.
+pub struct Ring {
+	inner: RingInner,
+}
+
 impl Ring {
-	/// Return a Ring created from a pointer if the pointer is not null
-	pub fn from_ptr(client_id: u16, rtype: RingType, r: *mut dpdk_ffi::rte_ring) -> Option<Self> {
-		if let Some(raw) = NonNull::new(r) {
-			Some(Self {
-				client_id,
-				rtype,
-				raw,
-			})
-		} else {
-			None
+	/// Look up a ring another process created by `name`
+	///
+	/// The ring stays with its creator, dropping the returned `Ring` does
+	/// not free it.
+	///
+	/// # Errors
+	///
+	/// If no ring goes by `name`, `RingError::NotFound` is returned.
+	pub fn lookup(name: &str) -> Fallible<Self> {
+		let raw = unsafe { dpdk_ffi::rte_ring_lookup(name.to_cstring().as_ptr()) };
+		match NonNull::new(raw) {
+			Some(raw) => Ok(Self {
+				inner: RingInner::Borrowed(raw),
+			}),
+			None => Err(RingError::NotFound(name.to_owned()).into()),
 		}
 	}
 
 	/// Creates a new `Ring` for `Mbuf`.
 	///
-	/// `capacity` is the maximum number of `Mbuf` the `Mempool` can hold.
-	/// The optimum size (in terms of memory usage) is when n is a power
-	/// of two minus one.
+	/// `capacity` is the number of slots in the ring. It must be a power
+	/// of two, and the ring holds at most `capacity - 1` `Mbuf`s.
+	///
+	/// `flags` is a combination of `RING_F_SP_ENQ` and `RING_F_SC_DEQ`, or
+	/// `RING_F_MP_MC` if several threads use either end of the ring.
 	///
 	/// `socket_id` is the socket where the memory should be allocated. The
 	/// value can be `SocketId::ANY` if there is no constraint.
 	///
 	/// # Errors
 	///
-	/// If allocation fails, then `DpdkError` is returned.
-	pub fn new(
-		client_id: u16,
-		rtype: RingType,
-		name: String,
-		capacity: usize,
-		socket_id: SocketId,
-	) -> Fallible<Self> {
+	/// If the capacity is not a power of two, `RingError::InvalidCapacity`
+	/// is returned. If allocation fails, then `DpdkError` is returned.
+	pub fn new(name: String, capacity: usize, flags: u32, socket_id: SocketId) -> Fallible<Self> {
+		Ring::validate_capacity(capacity)?;
 		let raw = unsafe {
 			dpdk_ffi::rte_ring_create(
 				name.clone().to_cstring().as_ptr(),
 				capacity as raw::c_uint,
 				socket_id.raw(),
-				NO_FLAGS as raw::c_uint,
+				flags as raw::c_uint,
 			)
 			.to_result(|_| DpdkError::new())?
 		};
 		info!("Created ring {}", name);
 		Ok(Self {
-			client_id,
-			rtype,
-			raw,
+			inner: RingInner::Owned(raw),
 		})
 	}
 
+	/// Check that `capacity` follows DPDK's sizing rules for rings
+	pub fn validate_capacity(capacity: usize) -> Fallible<()> {
+		if capacity.is_power_of_two() && capacity <= dpdk_ffi::RTE_RING_SZ_MASK as usize {
+			Ok(())
+		} else {
+			Err(RingError::InvalidCapacity(capacity).into())
+		}
+	}
+
 	/// Get the name to lookup with
 	#[inline]
-	pub fn name(&self) -> String {
-		let mut name: &str;
-		match self.rtype {
-			RingType::RX => name = "RX-".into(),
-			RingType::TX => name = "TX-".into(),
-		}
-		let id = format!("{}", self.client_id);
-		format!("{}{}", name, id)
-		// name
+	pub fn name(&self) -> &str {
+		self.raw().name[..].as_str()
 	}
 
 	/// Enqueue a single packet onto the ring
+	///
+	/// # Errors
+	///
+	/// If the ring is full, the packet is freed and `RingError::Full` is returned
 	pub fn enqueue(&mut self, pkt: Mbuf) -> Fallible<()> {
-		// match unsafe { dpdk_ffi::_rte_ring_enqueue(self.raw_mut(), pkt.into_ptr() as *mut c_void) }
-		// {
-		// 	0 => true,
-		// 	_ => false,
-		// }
-		unsafe {
-			dpdk_ffi::_rte_ring_enqueue(self.raw_mut(), pkt.into_ptr() as *mut c_void)
-				.to_result(|_| DpdkError::new())?
-		};
-		Ok(())
+		let ptr = pkt.into_ptr();
+		match unsafe { dpdk_ffi::_rte_ring_enqueue(self.raw_mut(), ptr as *mut c_void) } {
+			0 => Ok(()),
+			_ => {
+				// the ring did not take ownership, give the buffer back to the mempool
+				drop(unsafe { Mbuf::from_ptr(ptr) });
+				Err(RingError::Full(self.name().to_owned()).into())
+			}
+		}
 	}
 
 	/// Dequeue a single packet from the ring
-	pub fn dequeue(&mut self, pkt: &mut Mbuf) -> Fallible<()> {
-		unsafe {
-			dpdk_ffi::_rte_ring_dequeue(
+	///
+	/// # Errors
+	///
+	/// If there is nothing on the ring, `RingError::Empty` is returned
+	pub fn dequeue(&mut self) -> Fallible<Mbuf> {
+		let mut ptr: *mut c_void = ptr::null_mut();
+		match unsafe { dpdk_ffi::_rte_ring_dequeue(self.raw_mut(), &mut ptr) } {
+			0 => Ok(unsafe { Mbuf::from_ptr(ptr as *mut dpdk_ffi::rte_mbuf) }),
+			_ => Err(RingError::Empty(self.name().to_owned()).into()),
+		}
+	}
+
+	/// Enqueue as many packets as the ring has room for
+	///
+	/// The ring takes ownership of the packets it accepted. The packets that
+	/// did not fit are handed back, in order, for the caller to retry or free.
+	pub fn enqueue_burst(&mut self, pkts: Vec<Mbuf>) -> Vec<Mbuf> {
+		if pkts.is_empty() {
+			return pkts;
+		}
+		let ptrs = pkts
+			.into_iter()
+			.map(|pkt| pkt.into_ptr() as *mut c_void)
+			.collect::<Vec<_>>();
+		let sent = unsafe {
+			dpdk_ffi::_rte_ring_enqueue_burst(
 				self.raw_mut(),
-				&mut (pkt.get_ptr() as *mut _ as *mut c_void),
+				ptrs.as_ptr(),
+				ptrs.len() as raw::c_uint,
+				ptr::null_mut(),
 			)
-			.to_result(|_| DpdkError::new())?
-		};
-		Ok(())
+		} as usize;
+		ptrs[sent..]
+			.iter()
+			.map(|ptr| unsafe { Mbuf::from_ptr(*ptr as *mut dpdk_ffi::rte_mbuf) })
+			.collect()
+	}
+
+	/// Dequeue up to `max` packets from the ring
+	///
+	/// Returns an empty Vec if there is nothing on the ring
+	pub fn dequeue_burst(&mut self, max: usize) -> Vec<Mbuf> {
+		let mut ptrs: Vec<*mut c_void> = Vec::with_capacity(max);
+		unsafe {
+			let len = dpdk_ffi::_rte_ring_dequeue_burst(
+				self.raw_mut(),
+				ptrs.as_mut_ptr(),
+				max as raw::c_uint,
+				ptr::null_mut(),
+			);
+			ptrs.set_len(len as usize);
+		}
+		ptrs.into_iter()
+			.map(|ptr| unsafe { Mbuf::from_ptr(ptr as *mut dpdk_ffi::rte_mbuf) })
+			.collect()
+	}
+
+	/// Free every packet left on the ring
+	/// Returns the number of packets freed
+	pub fn drain(&mut self) -> usize {
+		let mut count = 0;
+		loop {
+			let pkts = self.dequeue_burst(DRAIN_BURST);
+			if pkts.is_empty() {
+				return count;
+			}
+			count += pkts.len();
+			Mbuf::free_bulk(pkts);
+		}
 	}
 
 	/// Returns the raw struct needed for FFI calls.
 	#[inline]
 	pub fn raw(&self) -> &dpdk_ffi::rte_ring {
-		unsafe { self.raw.as_ref() }
+		unsafe { self.inner.ptr().as_ref() }
 	}
 
 	/// Returns the raw struct needed for FFI calls.
 	#[inline]
 	pub fn raw_mut(&mut self) -> &mut dpdk_ffi::rte_ring {
-		unsafe { self.raw.as_mut() }
+		unsafe { self.inner.ptr_mut().as_mut() }
 	}
-
-	// Returns the name of the `Mempool`.
-	// #[inline]
-	// pub fn name(&self) -> &str {
-	// 	self.raw().name[..].as_str()
-	// }
 }
 
+// the ring lives in shared memory and can be used from any thread, the
+// enqueue and dequeue functions take `&mut self`
+unsafe impl Send for Ring {}
+unsafe impl Sync for Ring {}
+
 impl fmt::Debug for Ring {
 	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
 		let raw = self.raw();
 		unsafe {
-			f.debug_struct(&self.name()[..])
+			f.debug_struct(self.name())
 				.field("capacity", &raw.capacity)
 				.field("flags", &format_args!("{:#x}", raw.flags))
 				.finish()
@@ -165,9 +290,14 @@ impl fmt::Debug for Ring {
 
 impl Drop for Ring {
 	fn drop(&mut self) {
-		debug!("freeing {}.", self.name());
-		unsafe {
-			dpdk_ffi::rte_ring_free(self.raw_mut());
+		match self.inner {
+			RingInner::Owned(_) => {
+				debug!("freeing {}.", self.name());
+				unsafe {
+					dpdk_ffi::rte_ring_free(self.raw_mut());
+				}
+			}
+			RingInner::Borrowed(_) => debug!("releasing {}.", self.name()),
 		}
 	}
 }
@@ -175,25 +305,119 @@ impl Drop for Ring {
 /// The engine and client communicate with each other through
 /// a transmit and a receive Ring
 /// These two Rings together form a channel
+///
+/// Both ends count the packets they put on or take off the rings, RX being
+/// towards the client and TX from the client
 pub struct Channel {
 	pub(crate) tx_q: Ring,
 	pub(crate) rx_q: Ring,
+	rx_stats: Arc<Counters>,
+	tx_stats: Arc<Counters>,
 }
 
 impl Channel {
-	/// New Channel
-	pub fn new(tx_q: Ring, rx_q: Ring) -> Self {
-		Self { tx_q, rx_q }
+	/// New Channel of client `client_id`
+	pub fn new(client_id: u16, tx_q: Ring, rx_q: Ring) -> Self {
+		Self {
+			tx_q,
+			rx_q,
+			rx_stats: metrics::client_counters(client_id, Direction::Rx),
+			tx_stats: metrics::client_counters(client_id, Direction::Tx),
+		}
 	}
 
 	/// Send a packet
 	pub fn send(&mut self, pkt: Mbuf) -> Fallible<()> {
-		self.tx_q.enqueue(pkt)
+		Channel::put(&mut self.tx_q, &self.tx_stats, pkt)
 	}
 
 	/// Receive a packet
-	pub fn receive(&mut self, pkt: &mut Mbuf) -> Fallible<()> {
-		self.rx_q.dequeue(pkt)
+	pub fn receive(&mut self) -> Fallible<Mbuf> {
+		Channel::take(&mut self.rx_q, &self.rx_stats)
+	}
+
+	/// Send a burst of packets
+	/// Returns the packets that did not fit on the ring
+	pub fn send_burst(&mut self, pkts: Vec<Mbuf>) -> Vec<Mbuf> {
+		Channel::put_burst(&mut self.tx_q, &self.tx_stats, pkts)
+	}
+
+	/// Receive up to `max` packets
+	pub fn receive_burst(&mut self, max: usize) -> Vec<Mbuf> {
+		Channel::take_burst(&mut self.rx_q, &self.rx_stats, max)
+	}
+
+	/// Deliver a packet to the client, the engine's end of `receive`
+	pub(crate) fn deliver(&mut self, pkt: Mbuf) -> Fallible<()> {
+		Channel::put(&mut self.rx_q, &self.rx_stats, pkt)
+	}
+
+	/// Deliver a burst of packets to the client, the engine's end of `receive_burst`
+	/// Returns the packets that did not fit on the ring
+	pub(crate) fn deliver_burst(&mut self, pkts: Vec<Mbuf>) -> Vec<Mbuf> {
+		Channel::put_burst(&mut self.rx_q, &self.rx_stats, pkts)
+	}
+
+	/// Collect a packet the client sent, the engine's end of `send`
+	pub(crate) fn collect(&mut self) -> Fallible<Mbuf> {
+		Channel::take(&mut self.tx_q, &self.tx_stats)
+	}
+
+	/// Collect up to `max` packets the client sent, the engine's end of `send_burst`
+	pub(crate) fn collect_burst(&mut self, max: usize) -> Vec<Mbuf> {
+		Channel::take_burst(&mut self.tx_q, &self.tx_stats, max)
+	}
+
+	/// Record packets for or from the client that were dropped
+	pub(crate) fn record_dropped(&self, dir: Direction, cause: DropCause, packets: usize) {
+		match dir {
+			Direction::Rx => self.rx_stats.record_dropped(cause, packets),
+			Direction::Tx => self.tx_stats.record_dropped(cause, packets),
+		}
+	}
+
+	/// What this end counted in `dir` so far
+	pub(crate) fn totals(&self, dir: Direction) -> Totals {
+		match dir {
+			Direction::Rx => self.rx_stats.totals(),
+			Direction::Tx => self.tx_stats.totals(),
+		}
+	}
+
+	/// Free every packet left on both rings
+	/// Returns the number of packets freed
+	pub fn drain(&mut self) -> usize {
+		self.tx_q.drain() + self.rx_q.drain()
+	}
+
+	// the length is taken before the enqueue, the packet belongs to the peer after it
+	fn put(ring: &mut Ring, stats: &Counters, pkt: Mbuf) -> Fallible<()> {
+		let octets = pkt.data_len();
+		let res = ring.enqueue(pkt);
+		match res {
+			Ok(_) => stats.record(1, octets),
+			Err(_) => stats.record_dropped(DropCause::RingFull, 1),
+		}
+		res
+	}
+
+	fn put_burst(ring: &mut Ring, stats: &Counters, pkts: Vec<Mbuf>) -> Vec<Mbuf> {
+		let (count, octets) = (pkts.len(), metrics::octets(&pkts));
+		let unsent = ring.enqueue_burst(pkts);
+		stats.record(count - unsent.len(), octets - metrics::octets(&unsent));
+		unsent
+	}
+
+	fn take(ring: &mut Ring, stats: &Counters) -> Fallible<Mbuf> {
+		let pkt = ring.dequeue()?;
+		stats.record(1, pkt.data_len());
+		Ok(pkt)
+	}
+
+	fn take_burst(ring: &mut Ring, stats: &Counters, max: usize) -> Vec<Mbuf> {
+		let pkts = ring.dequeue_burst(max);
+		stats.record(pkts.len(), metrics::octets(&pkts));
+		pkts
 	}
 }
 
@@ -215,18 +439,47 @@ impl EngineRingMap {
 		Self { ring_map }
 	}
 	/// Send a packet to a container
+	///
+	/// `Channel::send` and `Channel::receive` are named from the container's side,
+	/// so the engine delivers onto the container's RX ring
 	pub fn send(&self, key: u16, pkt: Mbuf) -> Fallible<()> {
 		match self.ring_map.get_mut(&key) {
-			Some(mut ch) => ch.send(pkt),
+			Some(mut ch) => ch.deliver(pkt),
 			None => Err(format_err!("Failed to send packet")),
 		}
 	}
 
 	/// Receive a packet from a container
-	pub fn receive(&self, key: u16, pkt: &mut Mbuf) -> Fallible<()> {
+	///
+	/// The engine reads what the container put on its TX ring
+	pub fn receive(&self, key: u16) -> Fallible<Mbuf> {
 		match self.ring_map.get_mut(&key) {
-			Some(mut ch) => ch.receive(pkt),
+			Some(mut ch) => ch.collect(),
 			None => Err(format_err!("Failed to receive packet")),
 		}
 	}
+
+	/// Send a burst of packets to a container
+	/// Returns the packets that did not fit on the container's RX ring
+	pub fn send_burst(&self, key: u16, pkts: Vec<Mbuf>) -> Fallible<Vec<Mbuf>> {
+		match self.ring_map.get_mut(&key) {
+			Some(mut ch) => Ok(ch.deliver_burst(pkts)),
+			None => Err(format_err!("Failed to send packets")),
+		}
+	}
+
+	/// Receive up to `max` packets from a container
+	pub fn receive_burst(&self, key: u16, max: usize) -> Fallible<Vec<Mbuf>> {
+		match self.ring_map.get_mut(&key) {
+			Some(mut ch) => Ok(ch.collect_burst(max)),
+			None => Err(format_err!("Failed to receive packets")),
+		}
+	}
+
+	/// Record packets for or from a container that were dropped
+	pub fn record_dropped(&self, key: u16, dir: Direction, cause: DropCause, packets: usize) {
+		if let Some(ch) = self.ring_map.get(&key) {
+			ch.record_dropped(dir, cause, packets);
+		}
+	}
 }
@@ -10,15 +10,44 @@ use crate::{
 	ffi::{AsStr, ToCString, ToResult},
 	info,
 };
+use dpdk_ffi;
 use failure::{Fail, Fallible};
 use std::{fmt, os::raw};
 
 const NO_FLAGS: u8 = 0;
 
+/// Error indicating a `Memzone` operation failed
+#[derive(Debug, Fail)]
+pub enum MemzoneError {
+	/// No memzone was reserved under the name
+	#[fail(display = "Memzone {} not found.", _0)]
+	NotFound(String),
+}
+
+/// Memzones are shared between processes, only the one that reserved a zone frees it
+// memzone_reserve returns *const so NonNull not required (see ToResult trait)
+enum MemzoneInner {
+	/// Zone reserved by this process
+	/// Should be freed when goes out of scope
+	Owned(*const dpdk_ffi::rte_memzone),
+	/// Zone looked up from the process that reserved it
+	/// Left alone when goes out of scope
+	Borrowed(*const dpdk_ffi::rte_memzone),
+}
+
+impl MemzoneInner {
+	fn ptr(&self) -> *const dpdk_ffi::rte_memzone {
+		match *self {
+			MemzoneInner::Owned(raw) => raw,
+			MemzoneInner::Borrowed(raw) => raw,
+		}
+	}
+}
+
 /// A physical memory zone reserved
 /// rte_memzone.addr is the start of the virtual memory address
 pub struct Memzone {
-	raw: *const ffi::rte_memzone, // memzone_reserve returns *const so NonNull not required (see ToResult trait)
+	inner: MemzoneInner,
 }
 
 impl Memzone {
@@ -35,25 +64,58 @@ impl Memzone {
 	/// If allocation fails, then `DpdkError` is returned.
 	pub fn new(name: String, capacity: usize, socket_id: SocketId) -> Fallible<Self> {
 		let raw = unsafe {
-			ffi::rte_memzone_reserve(
+			dpdk_ffi::rte_memzone_reserve(
 				name.clone().to_cstring().as_ptr(),
-				capacity as u64,
+				capacity as dpdk_ffi::size_t,
 				socket_id.raw(),
 				NO_FLAGS as raw::c_uint,
 			)
 			.to_result(|_| DpdkError::new())?
 		};
 		info!("created {}", name);
-		Ok(Self { raw })
+		Ok(Self {
+			inner: MemzoneInner::Owned(raw),
+		})
+	}
+
+	/// Look up a memzone another process reserved by `name`
+	///
+	/// The zone stays with its creator, dropping the returned `Memzone` does
+	/// not free it.
+	///
+	/// # Errors
+	///
+	/// If no zone goes by `name`, `MemzoneError::NotFound` is returned.
+	pub fn lookup(name: &str) -> Fallible<Self> {
+		let raw = unsafe {
+			dpdk_ffi::rte_memzone_lookup(name.to_cstring().as_ptr())
+				.to_result(|_| MemzoneError::NotFound(name.to_owned()))?
+		};
+		Ok(Self {
+			inner: MemzoneInner::Borrowed(raw),
+		})
 	}
 
 	/// Returns the raw struct needed for FFI calls.
 	#[inline]
-	pub fn raw(&self) -> &ffi::rte_memzone {
-		unsafe { &*self.raw }
+	pub fn raw(&self) -> &dpdk_ffi::rte_memzone {
+		unsafe { &*self.inner.ptr() }
 	}
 
-	/// Returns the name of the `Mempool`.
+	/// Returns the virtual address the zone starts at.
+	#[inline]
+	pub fn addr(&self) -> *mut raw::c_void {
+		// rte_memzone is packed, the union is copied out before reading it
+		unsafe { { self.raw().__bindgen_anon_2 }.addr }
+	}
+
+	/// Returns the size of the zone in bytes.
+	#[inline]
+	pub fn len(&self) -> usize {
+		self.raw().len as usize
+	}
+
+	/// Returns the name of the `Memzone`.
 	#[inline]
 	pub fn name(&self) -> &str {
 		self.raw().name[..].as_str()
@@ -63,22 +125,26 @@ impl Memzone {
 impl fmt::Debug for Memzone {
 	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
 		let raw = self.raw();
-		unsafe {
-			f.debug_struct(self.name())
-				.field("capacity", &raw.len)
-				.field("hugepage_size", &raw.hugepage_sz)
-				.field("flags", &format_args!("{:#x}", raw.flags))
-				.field("socket", &raw.socket_id)
-				.finish()
-		}
+		// rte_memzone is packed, fields are copied out before formatting
+		f.debug_struct(self.name())
+			.field("capacity", &{ raw.len })
+			.field("hugepage_size", &{ raw.hugepage_sz })
+			.field("flags", &format_args!("{:#x}", { raw.flags }))
+			.field("socket", &{ raw.socket_id })
+			.finish()
 	}
 }
 
 impl Drop for Memzone {
 	fn drop(&mut self) {
-		debug!("freeing {}.", self.name());
-		unsafe {
-			ffi::rte_memzone_free(self.raw());
+		match self.inner {
+			MemzoneInner::Owned(raw) => {
+				debug!("freeing {}.", self.name());
+				unsafe {
+					dpdk_ffi::rte_memzone_free(raw);
+				}
+			}
+			MemzoneInner::Borrowed(_) => debug!("releasing {}.", self.name()),
 		}
 	}
 }
@@ -6,11 +6,13 @@
 mod mbuf;
 mod mempool;
 mod memring;
+mod memzone;
 mod port;
 
 pub use mbuf::*;
 pub use mempool::*;
 pub use memring::*;
+pub use memzone::*;
 pub use port::*;
 
 use crate::net::MacAddr;
@@ -18,7 +20,7 @@ use crate::{debug, ffi::*};
 use dpdk_ffi;
 use failure::{Fail, Fallible};
 use libc;
-use std::{cell::Cell, fmt, mem, os::raw};
+use std::{cell::Cell, env, fmt, mem, os::raw};
 
 /// An error generated in `libdpdk`.
 ///
@@ -51,13 +53,19 @@ impl DpdkError {
 /// A socket is also known as a NUMA node. On a multi-socket system, for best
 /// performance, ensure that the cores and memory used for packet processing
 /// are in the same socket as the network interface card
-#[derive(Copy, Clone, PartialEq, Eq, Hash)]
+#[derive(Copy, Clone, Eq, Hash, Ord, PartialEq, PartialOrd)]
 pub struct SocketId(raw::c_int);
 
 impl SocketId {
 	/// A socket id for any NUMA node
 	pub const ANY: Self = SocketId(-1);
 
+	/// Creates a new SocketId from the numeric ID assigned to the NUMA node
+	#[inline]
+	pub(crate) fn new(i: raw::c_int) -> SocketId {
+		SocketId(i)
+	}
+
 	/// Returns the ID of the socket the current core is on
 	#[inline]
 	pub fn current() -> Self {
@@ -167,6 +175,21 @@ pub(crate) fn eal_init(args: Vec<String>) -> Fallible<()> {
 	res.to_result(DpdkError::from_errno).map(|_| ())
 }
 
+/// Returns whether a primary process with the file prefix `prefix` is running
+///
+/// A secondary process can not initialize the EAL without its primary,
+/// check before calling `eal_init`
+pub(crate) fn primary_proc_alive(prefix: &str) -> bool {
+	// the runtime directory of DPDK 19.11, see eal_filesystem.h
+	let base = if unsafe { libc::getuid() } == 0 {
+		"/var/run".to_owned()
+	} else {
+		env::var("XDG_RUNTIME_DIR").unwrap_or_else(|_| "/tmp".to_owned())
+	};
+	let path = format!("{}/dpdk/{}/config", base, prefix);
+	unsafe { dpdk_ffi::rte_eal_primary_proc_alive(path.to_cstring().as_ptr()) == 1 }
+}
+
 /// Cleans up the Environment Abstraction Layer (EAL).
 pub(crate) fn eal_cleanup() -> Fallible<()> {
 	unsafe {
@@ -185,21 +208,47 @@ fn eth_macaddr_get(port_id: u16) -> MacAddr {
 	addr.addr_bytes.into()
 }
 
+/// Returns the link status of the port without waiting for the link to come up
+fn eth_link_get(port_id: u16) -> LinkStatus {
+	let link = unsafe {
+		let mut link: dpdk_ffi::rte_eth_link = mem::zeroed();
+		dpdk_ffi::rte_eth_link_get_nowait(port_id, &mut link);
+		link
+	};
+	LinkStatus {
+		up: link.link_status() as u32 == dpdk_ffi::ETH_LINK_UP,
+		speed: link.link_speed,
+		full_duplex: link.link_duplex() as u32 == dpdk_ffi::ETH_LINK_FULL_DUPLEX,
+		autoneg: link.link_autoneg() as u32 == dpdk_ffi::ETH_LINK_AUTONEG,
+	}
+}
+
 /// Frees the `rte_mbuf` in bulk
 pub(crate) fn mbuf_free_bulk(mbufs: Vec<*mut dpdk_ffi::rte_mbuf>) {
 	assert!(!mbufs.is_empty());
 
 	let mut to_free = Vec::with_capacity(mbufs.len());
-	let pool = unsafe { (*mbufs[0]).pool };
+	let mut pool = unsafe { (*mbufs[0]).pool };
 
 	for mbuf in mbufs.into_iter() {
+		// a shared buffer only drops its reference and a clone has to be detached,
+		// neither can go back to the mempool as is
+		if unsafe { dpdk_ffi::_rte_mbuf_cloned(mbuf) != 0 || dpdk_ffi::_rte_mbuf_refcnt_read(mbuf) > 1 } {
+			unsafe {
+				dpdk_ffi::_rte_pktmbuf_free(mbuf);
+			}
+			continue;
+		}
 		if pool == unsafe { (*mbuf).pool } {
 			to_free.push(mbuf as *mut raw::c_void);
 		} else {
+			// a burst can mix the mempools of several sockets,
+			// each run goes back to the pool it came from
 			unsafe {
 				let len = to_free.len();
 				dpdk_ffi::_rte_mempool_put_bulk(pool, to_free.as_ptr(), len as u32);
 				to_free.set_len(0);
+				pool = (*mbuf).pool;
 			}
 			to_free.push(mbuf as *mut raw::c_void);
 		}
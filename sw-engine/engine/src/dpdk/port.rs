@@ -10,12 +10,22 @@ use crate::{
 	ensure,
 	ffi::{AsStr, ToCString, ToResult},
 	info,
-	// metrics::{labels, SINK},
+	metrics::{self, Counters, Direction, DropCause},
 	net::MacAddr,
 	warn,
 };
+use crossbeam_queue::SegQueue;
 use failure::{Fail, Fallible};
-use std::{collections::HashMap, fmt, marker::Sync, os::raw, ptr};
+use once_cell::sync::Lazy;
+use std::{
+	collections::{BTreeMap, HashMap},
+	fmt,
+	marker::Sync,
+	mem,
+	os::raw,
+	ptr,
+	sync::Arc,
+};
 
 const DEFAULT_RSS_HF: u64 =
 	(dpdk_ffi::ETH_RSS_IP | dpdk_ffi::ETH_RSS_TCP | dpdk_ffi::ETH_RSS_UDP | dpdk_ffi::ETH_RSS_SCTP)
@@ -99,10 +109,9 @@ pub struct PortQueue {
 	port_id: PortId,
 	rxq: RxQueueIndex,
 	txq: TxQueueIndex,
-	// statistics
-	// received: Option<Counter>,
-	// transmitted: Option<Counter>,
-	// dropped: Option<Counter>,
+	// statistics, the TX drops are counted with the transmitted packets
+	received: Option<Arc<Counters>>,
+	transmitted: Option<Arc<Counters>>,
 }
 
 impl PortQueue {
@@ -111,9 +120,8 @@ impl PortQueue {
 			port_id: port,
 			rxq,
 			txq,
-			// received: None,
-			// transmitted: None,
-			// dropped: None,
+			received: None,
+			transmitted: None,
 		}
 	}
 
@@ -136,18 +144,23 @@ impl PortQueue {
 				RX_BURST_MAX as u16,
 			)
 		};
-		// self.received.as_ref().unwrap().record(len as u64);
 
-		unsafe {
+		let pkts = unsafe {
 			ptrs.set_len(len as usize);
 			ptrs.into_iter()
 				.map(|ptr| Mbuf::from_ptr(ptr))
 				.collect::<Vec<_>>()
+		};
+		if let Some(counters) = &self.received {
+			counters.record(pkts.len(), metrics::octets(&pkts));
 		}
+		pkts
 	}
 
 	/// Send packets to the transmit queue
 	pub fn transmit(&self, packets: Vec<Mbuf>) {
+		// the lengths are taken up front, sent mbufs belong to the driver
+		let mut lens = packets.iter().map(Mbuf::data_len).collect::<Vec<_>>();
 		let mut ptrs = packets.into_iter().map(Mbuf::into_ptr).collect::<Vec<_>>();
 
 		loop {
@@ -157,7 +170,10 @@ impl PortQueue {
 			};
 
 			if sent > 0 {
-				// self.transmitted.as_ref().record(sent as u64);
+				let octets = lens.drain(..sent as usize).sum();
+				if let Some(counters) = &self.transmitted {
+					counters.record(sent as usize, octets);
+				}
 
 				if to_send - sent > 0 {
 					// still have packets not sent
@@ -172,7 +188,9 @@ impl PortQueue {
 			} else {
 				// tx queue is full and we can't make progress
 				// start dropping packets to avoid potentially stuck in an endless loop
-				// self.dropped.as_ref().record(ptrs.len() as u64);
+				if let Some(counters) = &self.transmitted {
+					counters.record_dropped(DropCause::TxQueueFull, ptrs.len());
+				}
 				super::mbuf_free_bulk(ptrs);
 				break;
 			}
@@ -183,46 +201,93 @@ impl PortQueue {
 	/// Some device drivers don't track TX and RX packets per queue
 	/// Instead we will track them here for all devices
 	/// Additionally we also track the TX packet drops when the TX queue is full
-	// fn set_counters(&mut self, port: &str, core_id: CoreId) {
-	// let counter = SINK.scoped("port").counter_with_labels(
-	// 	"packets",
-	// 	labels!(
-	// 		"port" => port.to_owned(),
-	// 		"dir" => "rx",
-	// 		"core" => core_id.0.to_string(),
-	// 	),
-	// );
-	// self.received = Some(counter);
-
-	// let counter = SINK.scoped("port").counter_with_labels(
-	// 	"packets",
-	// 	labels!(
-	// 		"port" => port.to_owned(),
-	// 		"dir" => "tx",
-	// 		"core" => core_id.0.to_string(),
-	// 	),
-	// );
-	// self.transmitted = Some(counter);
-
-	// let counter = SINK.scoped("port").counter_with_labels(
-	// 	"dropped",
-	// 	labels!(
-	// 		"port" => port.to_owned(),
-	// 		"dir" => "tx",
-	// 		"core" => core_id.0.to_string(),
-	// 	),
-	// );
-	// self.dropped = Some(counter);
-	// }
+	fn set_counters(&mut self, port: &str) {
+		self.received = Some(metrics::port_counters(port, Direction::Rx));
+		self.transmitted = Some(metrics::port_counters(port, Direction::Tx));
+	}
+
+	/// Records packets for the port dropped before reaching its queues
+	pub fn record_dropped(&self, dir: Direction, cause: DropCause, packets: usize) {
+		let counters = match dir {
+			Direction::Rx => &self.received,
+			Direction::Tx => &self.transmitted,
+		};
+		if let Some(counters) = counters {
+			counters.record_dropped(cause, packets);
+		}
+	}
 
 	/// Returns the MAC address of the port.
 	pub fn mac_addr(&self) -> MacAddr {
 		super::eth_macaddr_get(self.port_id.0)
 	}
+
+	/// Returns the link status of the port.
+	pub fn link_status(&self) -> LinkStatus {
+		super::eth_link_get(self.port_id.0)
+	}
 }
 
 unsafe impl Sync for PortQueue {}
 
+/// The state of the link of a port
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct LinkStatus {
+	pub up: bool,
+	/// Speed in Mbps, 0 if unknown
+	pub speed: u32,
+	pub full_duplex: bool,
+	pub autoneg: bool,
+}
+
+impl fmt::Display for LinkStatus {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		if !self.up {
+			return write!(f, "down");
+		}
+		write!(
+			f,
+			"up {} Mbps {}-duplex{}",
+			self.speed,
+			if self.full_duplex { "full" } else { "half" },
+			if self.autoneg { " autoneg" } else { "" }
+		)
+	}
+}
+
+/// A link state change reported by the LSC interrupt of a port
+#[derive(Clone, Copy, Debug)]
+pub struct LinkEvent {
+	pub port_id: u16,
+	pub status: LinkStatus,
+}
+
+/// Link state changes reported on the interrupt thread, until taken
+static LINK_EVENTS: Lazy<SegQueue<LinkEvent>> = Lazy::new(SegQueue::new);
+
+/// Takes the link state changes reported since the last call
+pub fn link_events() -> Vec<LinkEvent> {
+	let mut events = Vec::new();
+	while let Some(event) = LINK_EVENTS.pop() {
+		events.push(event);
+	}
+	events
+}
+
+// runs on the EAL interrupt thread, must not block
+unsafe extern "C" fn on_link_change(
+	port_id: u16,
+	_event: dpdk_ffi::rte_eth_event_type::Type,
+	_cb_arg: *mut raw::c_void,
+	_ret_param: *mut raw::c_void,
+) -> raw::c_int {
+	LINK_EVENTS.push(LinkEvent {
+		port_id,
+		status: super::eth_link_get(port_id),
+	});
+	0
+}
+
 /// Error indicating failed to initialize the port.
 #[derive(Debug, Fail)]
 pub enum PortError {
@@ -240,6 +305,33 @@ pub enum PortError {
 	/// the number of cores assigned to the port
 	#[fail(display = "Insufficient number of TX queues '{}'.", _0)]
 	InsufficientTxQueues(usize),
+	/// The device does not raise link state change interrupts
+	#[fail(display = "Port {} does not report link state changes.", _0)]
+	LscUnsupported(String),
+}
+
+/// The basic hardware counters of a port
+///
+/// The NIC counts what it received and sent, and the packets it dropped
+/// before the engine ever saw them
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct PortStats {
+	/// Packets received
+	pub ipackets: u64,
+	/// Packets transmitted
+	pub opackets: u64,
+	/// Bytes received
+	pub ibytes: u64,
+	/// Bytes transmitted
+	pub obytes: u64,
+	/// Packets dropped by the NIC because the RX queues were full
+	pub imissed: u64,
+	/// Erroneous packets received
+	pub ierrors: u64,
+	/// Packets that failed to transmit
+	pub oerrors: u64,
+	/// Packets dropped because no mbuf was left to receive them into
+	pub rx_nombuf: u64,
 }
 
 /// An Ethernet device port
@@ -249,6 +341,13 @@ pub struct Port {
 	device: String,
 	queues: HashMap<CoreId, PortQueue>,
 	dev_info: dpdk_ffi::rte_eth_dev_info,
+	watching_link: bool,
+}
+
+/// Whether the device raises link state change interrupts
+fn supports_lsc(dev_info: &dpdk_ffi::rte_eth_dev_info) -> bool {
+	!dev_info.dev_flags.is_null()
+		&& unsafe { *dev_info.dev_flags } & dpdk_ffi::RTE_ETH_DEV_INTR_LSC != 0
 }
 
 impl Port {
@@ -294,6 +393,112 @@ impl Port {
 		}
 		info!("stopped port {}.", self.name());
 	}
+
+	/// Returns the link status of the port
+	pub fn link_status(&self) -> LinkStatus {
+		super::eth_link_get(self.id.0)
+	}
+
+	/// Reports the link state changes of the port through `link_events`
+	///
+	/// # Errors
+	/// If the device does not raise link state change interrupts,
+	/// `PortError::LscUnsupported` is returned and its link has to be polled
+	pub fn watch_link(&mut self) -> Fallible<()> {
+		ensure!(
+			supports_lsc(&self.dev_info),
+			PortError::LscUnsupported(self.name.clone())
+		);
+		if !self.watching_link {
+			unsafe {
+				dpdk_ffi::rte_eth_dev_callback_register(
+					self.id.0,
+					dpdk_ffi::rte_eth_event_type::RTE_ETH_EVENT_INTR_LSC,
+					Some(on_link_change),
+					ptr::null_mut(),
+				)
+				.to_result(DpdkError::from_errno)?;
+			}
+			self.watching_link = true;
+			debug!("watching the link of port {}.", self.name());
+		}
+		Ok(())
+	}
+
+	/// Returns the basic hardware counters of the port
+	///
+	/// # Errors
+	/// If the device does not report statistics, `DpdkError` is returned
+	pub fn stats(&self) -> Fallible<PortStats> {
+		let stats = unsafe {
+			let mut stats: dpdk_ffi::rte_eth_stats = mem::zeroed();
+			dpdk_ffi::rte_eth_stats_get(self.id.0, &mut stats).to_result(DpdkError::from_errno)?;
+			stats
+		};
+		Ok(PortStats {
+			ipackets: stats.ipackets,
+			opackets: stats.opackets,
+			ibytes: stats.ibytes,
+			obytes: stats.obytes,
+			imissed: stats.imissed,
+			ierrors: stats.ierrors,
+			oerrors: stats.oerrors,
+			rx_nombuf: stats.rx_nombuf,
+		})
+	}
+
+	/// Returns the extended statistics of the port by name
+	///
+	/// The names and their number depend on the driver
+	///
+	/// # Errors
+	/// If the device does not report statistics, `DpdkError` is returned
+	pub fn xstats(&self) -> Fallible<BTreeMap<String, u64>> {
+		unsafe {
+			// asking with no room returns the number of xstats
+			let len = dpdk_ffi::rte_eth_xstats_get_names(self.id.0, ptr::null_mut(), 0)
+				.to_result(DpdkError::from_errno)? as usize;
+
+			let mut names: Vec<dpdk_ffi::rte_eth_xstat_name> = vec![mem::zeroed(); len];
+			let len = dpdk_ffi::rte_eth_xstats_get_names(
+				self.id.0,
+				names.as_mut_ptr(),
+				len as raw::c_uint,
+			)
+			.to_result(DpdkError::from_errno)? as usize;
+			names.truncate(len);
+
+			let mut values: Vec<dpdk_ffi::rte_eth_xstat> = vec![mem::zeroed(); len];
+			let len =
+				dpdk_ffi::rte_eth_xstats_get(self.id.0, values.as_mut_ptr(), len as raw::c_uint)
+					.to_result(DpdkError::from_errno)? as usize;
+			// more xstats than names if the device changed in between
+			values.truncate(len.min(names.len()));
+
+			Ok(values
+				.iter()
+				.filter_map(|xstat| {
+					names
+						.get(xstat.id as usize)
+						.map(|name| (name.name[..].as_str().to_owned(), xstat.value))
+				})
+				.collect())
+		}
+	}
+
+	/// Resets the basic and extended statistics of the port
+	///
+	/// # Errors
+	/// If the device does not support resetting its statistics, `DpdkError`
+	/// is returned
+	pub fn reset_stats(&self) -> Fallible<()> {
+		unsafe {
+			dpdk_ffi::rte_eth_stats_reset(self.id.0).to_result(DpdkError::from_errno)?;
+			dpdk_ffi::rte_eth_xstats_reset(self.id.0).to_result(DpdkError::from_errno)?;
+		}
+		debug!("reset the statistics of port {}.", self.name());
+		Ok(())
+	}
 }
 
 impl fmt::Debug for Port {
@@ -318,6 +523,14 @@ impl Drop for Port {
 		debug!("freeing {}.", self.name);
 
 		unsafe {
+			if self.watching_link {
+				dpdk_ffi::rte_eth_dev_callback_unregister(
+					self.id.0,
+					dpdk_ffi::rte_eth_event_type::RTE_ETH_EVENT_INTR_LSC,
+					Some(on_link_change),
+					ptr::null_mut(),
+				);
+			}
 			dpdk_ffi::rte_eth_dev_close(self.id.0);
 		}
 	}
@@ -460,12 +673,14 @@ impl<'a> PortBuilder<'a> {
 				DEFAULT_RSS_HF & self.dev_info.flow_type_rss_offloads;
 		}
 
-		// turns on optimization for fast release of mbufs
-		if self.dev_info.tx_offload_capa & dpdk_ffi::DEV_TX_OFFLOAD_MBUF_FAST_FREE as u64 > 0 {
-			conf.txmode.offloads |= dpdk_ffi::DEV_TX_OFFLOAD_MBUF_FAST_FREE as u64;
-			debug!("turned on optimization for fast release of mbufs");
+		// link state change interrupts, for the devices that raise them
+		if supports_lsc(&self.dev_info) {
+			conf.intr_conf.set_lsc(1);
 		}
 
+		// NOTE: DEV_TX_OFFLOAD_MBUF_FAST_FREE is not turned on, it requires every
+		// mbuf to have a reference count of 1 and flooding shares mbufs between ports
+
 		// must configure the device first before everything else
 		unsafe {
 			dpdk_ffi::rte_eth_dev_configure(self.port_id.0, len, len, &conf)
@@ -524,7 +739,7 @@ impl<'a> PortBuilder<'a> {
 			}
 			let mut q = PortQueue::new(self.port_id, rxq, txq);
 
-			// q.set_counters(&self.name, core_id);
+			q.set_counters(&self.name);
 			queues.insert(core_id, q);
 			debug!("initialized port queue for {:?}.", core_id);
 		}
@@ -544,6 +759,34 @@ impl<'a> PortBuilder<'a> {
 			device: self.device.clone(),
 			queues,
 			dev_info: self.dev_info,
+			watching_link: false,
 		})
 	}
 }
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn display_link_status() {
+		assert_eq!("down", LinkStatus::default().to_string());
+		let link = LinkStatus {
+			up: true,
+			speed: 10_000,
+			full_duplex: true,
+			autoneg: true,
+		};
+		assert_eq!("up 10000 Mbps full-duplex autoneg", link.to_string());
+		let link = LinkStatus {
+			up: true,
+			speed: 100,
+			full_duplex: false,
+			autoneg: false,
+		};
+		assert_eq!("up 100 Mbps half-duplex", link.to_string());
+		// nothing but the state is shown for a link that is down
+		let link = LinkStatus { up: false, ..link };
+		assert_eq!("down", link.to_string());
+	}
+}
@@ -10,12 +10,14 @@ pub mod config;
 mod dpdk;
 mod ffi;
 mod macros;
-// pub mod metrics;
+pub mod metrics;
 pub mod dockerlib;
 pub mod net;
 pub mod runtime;
 
-use crate::dpdk::{Mbuf, PortQueue};
+pub use crate::dpdk::Mbuf;
+
+use crate::dpdk::PortQueue;
 use crate::net::{FiveTuple, PortIdMbuf, RoutingTable};
 use dashmap::DashMap;
 use state;
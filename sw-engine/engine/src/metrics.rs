@@ -16,8 +16,7 @@
 * SPDX-License-Identifier: Apache-2.0
 */
 
-//! Exposes framework metrics, including port, kni, mempool, and pipeline
-//! metrics.
+//! Exposes engine metrics, including port and client metrics.
 //!
 //! # Port Metrics
 //!
@@ -25,29 +24,29 @@
 //! packets.
 //! * `port.octets`, total number of successfully received or transmitted
 //! bytes.
-//! * `port.dropped`, total number of packets dropped because the receive
-//! or transmit queues are full.
-//! * `port.errors`, total number of erroneous received packets or packets
-//! failed to transmit.
-//! * `port.no_mbuf`, total number of packets dropped due to mbuf allocation
-//! failures.
+//! * `port.dropped`, total number of packets dropped, labeled with the
+//! reason.
 //!
-//! Each metric is labeled with the port name and a direction, which can be
-//! either RX or TX. `port.packets` and `port.dropped` are tracked per core
-//! and labeled with the core id. The others are tracked by only the overall
-//! metrics.
+//! Each metric is labeled with the port name, a direction, which can be
+//! either RX or TX, and the core id.
 //!
+//! * `port.link_up`, 1 if the link of the port is up, 0 otherwise.
+//! * `port.link_speed`, speed of the link in Mbps.
 //!
-//! # KNI Metrics
+//! The link metrics are labeled with the port name.
 //!
-//! * `kni.packets`, total number of successfully received or transmitted
-//! packets.
-//! * `kni.octets`, total number of successfully received or transmitted bytes.
-//! * `kni.dropped`, total number of packets dropped because the transmit
-//! queue is full.
 //!
-//! Each metric is labeled with the KNI interface name and a direction, which
-//! can be either RX or TX.
+//! # Client Metrics
+//!
+//! * `client.packets`, total number of packets put on or taken off the
+//! client's rings. RX is towards the client, TX is from the client.
+//! * `client.octets`, total number of bytes put on or taken off the client's
+//! rings.
+//! * `client.dropped`, total number of packets for the client that were
+//! dropped, labeled with the reason.
+//!
+//! Each metric is labeled with the client id, a direction, which can be
+//! either RX or TX, and the core id.
 //!
 //!
 //! # Mempool Metrics
@@ -59,82 +58,501 @@
 //! Each metric is labeled with the mempool name.
 //!
 //!
-//! # Pipeline Metrics
+//! # Forwarding Database Metrics
+//!
+//! * `fdb.entries`, number of MACs in the forwarding database.
+//!
 //!
-//! * `pipeline.runs`, total number of times the pipeline executes.
-//! * `pipeline.processed`, total number of successfully processed packets.
-//! * `pipeline.dropped`, total number of packets intentionally dropped.
-//! * `pipeline.errors`, total number of packet dropped due to processing
-//! errors.
+//! # Drop Reasons
 //!
-//! Each metric is tracked per core and labeled with the core id and the
-//! pipeline name. If the pipeline doesn't have a name, it will be labeled
-//! as "default".
+//! * `ring_full`, the client's RX ring had no room left.
+//! * `unknown_dst`, the destination is unknown and there was no one to
+//! flood to.
+//! * `no_mbuf`, no mbuf could be allocated to copy the packet.
+//! * `tx_queue_full`, the port's transmit queue made no progress.
+//! * `not_ready`, the client has not reported ready or is dead.
+//! * `link_down`, the link of the port is down.
+//!
+//! The counters are kept per core and updated without locks. They are summed
+//! across the queues of a port when collected. Threads not pinned to a core
+//! share the `any` core.
+//!
+//! The metrics are served in the Prometheus text format, with the dots in
+//! the names replaced by underscores, e.g. `port_packets`.
+
+use crate::dpdk::{CoreId, Mbuf};
+use once_cell::sync::Lazy;
+use std::{
+    collections::BTreeMap,
+    fmt::Write,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex, Weak,
+    },
+};
+
+/// Number of per core slots, one per lcore and one shared by the threads
+/// not pinned to a core.
+const CORE_SLOTS: usize = dpdk_ffi::RTE_MAX_LCORE as usize + 1;
 
-// re-export some metrics types to make feature gated imports easier.
-pub(crate) use metrics_core::{labels, Key};
-pub(crate) use metrics_runtime::data::Counter;
-pub(crate) use metrics_runtime::Measurement;
+/// Number of drop reasons.
+const DROP_CAUSES: usize = 6;
 
-use crate::dpdk::{Mempool, MempoolStats, Port};
-use crate::warn;
-use failure::{format_err, Fallible};
-use metrics_runtime::{Receiver, Sink};
-use once_cell::sync::{Lazy, OnceCell};
+/// The direction of the packets counted.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Direction {
+    /// Received from a port, or delivered to a client.
+    Rx,
+    /// Transmitted out of a port, or sent by a client.
+    Tx,
+}
+
+impl Direction {
+    /// Returns the label value of the direction.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Direction::Rx => "rx",
+            Direction::Tx => "tx",
+        }
+    }
+}
+
+/// The reason a packet was dropped.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DropCause {
+    RingFull,
+    UnknownDst,
+    NoMbuf,
+    TxQueueFull,
+    NotReady,
+    LinkDown,
+}
+
+impl DropCause {
+    /// All the drop reasons, in slot order.
+    pub const ALL: [DropCause; DROP_CAUSES] = [
+        DropCause::RingFull,
+        DropCause::UnknownDst,
+        DropCause::NoMbuf,
+        DropCause::TxQueueFull,
+        DropCause::NotReady,
+        DropCause::LinkDown,
+    ];
+
+    /// Returns the label value of the reason.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            DropCause::RingFull => "ring_full",
+            DropCause::UnknownDst => "unknown_dst",
+            DropCause::NoMbuf => "no_mbuf",
+            DropCause::TxQueueFull => "tx_queue_full",
+            DropCause::NotReady => "not_ready",
+            DropCause::LinkDown => "link_down",
+        }
+    }
+
+    fn index(self) -> usize {
+        self as usize
+    }
+}
 
-/// The metrics store.
-static RECEIVER: OnceCell<Receiver> = OnceCell::new();
+/// The counters of one core, on a cache line of their own so cores do not
+/// contend for it.
+#[repr(align(64))]
+#[derive(Default)]
+struct CoreSlot {
+    packets: AtomicU64,
+    octets: AtomicU64,
+    dropped: [AtomicU64; DROP_CAUSES],
+}
 
-/// Safely initializes the metrics store. Because the receiver builder could
-/// potentially fail, the `Lazy` convenience type is not safe.
+/// Packet, byte and drop counters of one direction of a port or client.
 ///
-/// Also very important that `init` is not called twice.
-pub(crate) fn init() -> Fallible<()> {
-    let receiver = Receiver::builder().build()?;
+/// Every core counts in its own slot, recording is a relaxed atomic add.
+pub struct Counters {
+    cores: Box<[CoreSlot]>,
+}
+
+/// The counters summed across the cores.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Totals {
+    pub packets: u64,
+    pub octets: u64,
+    pub dropped: [u64; DROP_CAUSES],
+}
+
+impl Totals {
+    /// Returns the number of packets dropped for `cause`.
+    pub fn dropped(&self, cause: DropCause) -> u64 {
+        self.dropped[cause.index()]
+    }
 
-    RECEIVER
-        .set(receiver)
-        .map_err(|_| format_err!("already initialized."))?;
-    Ok(())
+    fn add(&mut self, other: &Totals) {
+        self.packets += other.packets;
+        self.octets += other.octets;
+        for (total, count) in self.dropped.iter_mut().zip(other.dropped.iter()) {
+            *total += count;
+        }
+    }
 }
 
-/// Registers DPDK collected port stats with the metrics store.
-pub(crate) fn register_port_stats(ports: &[Port]) {
-    let stats = ports.iter().map(Port::stats).collect::<Vec<_>>();
-    SINK.clone().proxy("port", move || {
-        stats
+impl Counters {
+    fn new() -> Self {
+        Counters {
+            cores: (0..CORE_SLOTS).map(|_| CoreSlot::default()).collect(),
+        }
+    }
+
+    #[inline]
+    fn slot(&self) -> &CoreSlot {
+        // the configured cores are below RTE_MAX_LCORE, only the threads
+        // not pinned to a core land in the last slot
+        let core = CoreId::current().raw();
+        &self.cores[core.min(CORE_SLOTS - 1)]
+    }
+
+    /// Records packets that went through.
+    #[inline]
+    pub fn record(&self, packets: usize, octets: usize) {
+        let slot = self.slot();
+        slot.packets.fetch_add(packets as u64, Ordering::Relaxed);
+        slot.octets.fetch_add(octets as u64, Ordering::Relaxed);
+    }
+
+    /// Records packets dropped for `cause`.
+    #[inline]
+    pub fn record_dropped(&self, cause: DropCause, packets: usize) {
+        self.slot().dropped[cause.index()].fetch_add(packets as u64, Ordering::Relaxed);
+    }
+
+    /// Returns the counters summed across the cores.
+    pub fn totals(&self) -> Totals {
+        let mut totals = Totals::default();
+        for (_, core) in self.per_core() {
+            totals.add(&core);
+        }
+        totals
+    }
+
+    /// Returns the counters of each core that counted anything, by core
+    /// slot. The last slot is shared by the threads not pinned to a core.
+    pub fn per_core(&self) -> Vec<(usize, Totals)> {
+        self.cores
             .iter()
-            .flat_map(|s| {
-                s.collect().unwrap_or_else(|err| {
-                    warn!(message = "failed to collect stats.", port = s.name(), ?err);
-                    Vec::new()
-                })
+            .enumerate()
+            .map(|(idx, slot)| {
+                let mut totals = Totals {
+                    packets: slot.packets.load(Ordering::Relaxed),
+                    octets: slot.octets.load(Ordering::Relaxed),
+                    ..Totals::default()
+                };
+                for (total, count) in totals.dropped.iter_mut().zip(slot.dropped.iter()) {
+                    *total = count.load(Ordering::Relaxed);
+                }
+                (idx, totals)
             })
+            .filter(|(_, totals)| *totals != Totals::default())
             .collect()
-    });
+    }
 }
 
-/// Registers collected mempool stats with the metrics store.
-pub(crate) fn register_mempool_stats(mempools: &[Mempool]) {
-    let stats = mempools.iter().map(Mempool::stats).collect::<Vec<_>>();
-    SINK.clone().proxy("mempool", move || {
-        stats.iter().flat_map(MempoolStats::collect).collect()
-    });
+fn core_label(slot: usize) -> String {
+    if slot == CORE_SLOTS - 1 {
+        "any".to_owned()
+    } else {
+        slot.to_string()
+    }
 }
 
-/// Returns the global metrics store.
-///
-/// Metrics are managed using [metrics-rs]. The application can use this to
-/// access framework metrics or to add new application metrics.
-///
-/// # Panics
+/// Returns the number of bytes in `pkts`.
+#[inline]
+pub(crate) fn octets(pkts: &[Mbuf]) -> usize {
+    pkts.iter().map(Mbuf::data_len).sum()
+}
+
+/// What the counters are for, the scope, label name and label value.
+type Source = (&'static str, &'static str, String, Direction);
+
+/// The registered counters, dropped counters are pruned when collecting.
+static REGISTRY: Lazy<Mutex<Vec<(Source, Weak<Counters>)>>> = Lazy::new(|| Mutex::new(Vec::new()));
+
+fn register(source: Source) -> Arc<Counters> {
+    let counters = Arc::new(Counters::new());
+    if let Ok(mut registry) = REGISTRY.lock() {
+        registry.push((source, Arc::downgrade(&counters)));
+    }
+    counters
+}
+
+/// Returns new counters for port `name`, one for each of its queues.
+pub(crate) fn port_counters(name: &str, dir: Direction) -> Arc<Counters> {
+    register(("port", "port", name.to_owned(), dir))
+}
+
+/// Returns new counters for client `id`.
+pub(crate) fn client_counters(id: u16, dir: Direction) -> Arc<Counters> {
+    register(("client", "client", id.to_string(), dir))
+}
+
+/// Whether a metric only goes up or goes up and down.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Kind {
+    Counter,
+    Gauge,
+}
+
+impl Kind {
+    fn as_str(self) -> &'static str {
+        match self {
+            Kind::Counter => "counter",
+            Kind::Gauge => "gauge",
+        }
+    }
+}
+
+/// A single metric value.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Measurement {
+    /// Name of the metric, e.g. `port.packets`.
+    pub name: String,
+    pub kind: Kind,
+    /// Label names and values.
+    pub labels: Vec<(&'static str, String)>,
+    pub value: u64,
+}
+
+impl Measurement {
+    /// Returns a gauge measurement.
+    pub fn gauge(name: &str, labels: Vec<(&'static str, String)>, value: u64) -> Self {
+        Measurement {
+            name: name.to_owned(),
+            kind: Kind::Gauge,
+            labels,
+            value,
+        }
+    }
+
+    fn counter(name: String, labels: Vec<(&'static str, String)>, value: u64) -> Self {
+        Measurement {
+            name,
+            kind: Kind::Counter,
+            labels,
+            value,
+        }
+    }
+}
+
+/// Collects the current value of every counter.
 ///
-/// Panics if `Runtime::build` is not called first.
+/// The counters of the same port or client and core are summed across the
+/// queues. Ports and clients are listed in label order.
+pub fn collect() -> Vec<Measurement> {
+    let mut sums: BTreeMap<(Source, String), Totals> = BTreeMap::new();
+    if let Ok(mut registry) = REGISTRY.lock() {
+        // forget the counters of ports and clients that are gone
+        registry.retain(|(_, counters)| counters.strong_count() > 0);
+        for (source, counters) in registry.iter() {
+            if let Some(counters) = counters.upgrade() {
+                for (slot, totals) in counters.per_core() {
+                    sums.entry((source.clone(), core_label(slot)))
+                        .or_insert_with(Totals::default)
+                        .add(&totals);
+                }
+            }
+        }
+    }
+
+    let mut measurements = Vec::new();
+    for (((scope, label, value, dir), core), totals) in sums {
+        let labels = vec![
+            (label, value),
+            ("dir", dir.as_str().to_owned()),
+            ("core", core),
+        ];
+        measurements.push(Measurement::counter(
+            format!("{}.packets", scope),
+            labels.clone(),
+            totals.packets,
+        ));
+        measurements.push(Measurement::counter(
+            format!("{}.octets", scope),
+            labels.clone(),
+            totals.octets,
+        ));
+        for cause in DropCause::ALL.iter() {
+            let mut labels = labels.clone();
+            labels.push(("reason", cause.as_str().to_owned()));
+            measurements.push(Measurement::counter(
+                format!("{}.dropped", scope),
+                labels,
+                totals.dropped(*cause),
+            ));
+        }
+    }
+    measurements
+}
+
+/// Renders measurements in the Prometheus text exposition format.
 ///
-/// [metrics-rs]: https://github.com/metrics-rs
-pub fn global() -> &'static Receiver {
-    unsafe { RECEIVER.get_unchecked() }
+/// The measurements of the same metric are grouped under a single `# TYPE`
+/// line, in the order the metrics first appear.
+pub fn encode(measurements: &[Measurement]) -> String {
+    let mut families: Vec<(&str, Vec<&Measurement>)> = Vec::new();
+    for m in measurements {
+        match families.iter_mut().find(|(name, _)| *name == m.name) {
+            Some((_, family)) => family.push(m),
+            None => families.push((m.name.as_str(), vec![m])),
+        }
+    }
+
+    let mut text = String::new();
+    for (name, family) in families {
+        let name = name.replace('.', "_");
+        let _ = writeln!(text, "# TYPE {} {}", name, family[0].kind.as_str());
+        for m in family {
+            text.push_str(&name);
+            if !m.labels.is_empty() {
+                let labels = m
+                    .labels
+                    .iter()
+                    .map(|(key, value)| format!("{}=\"{}\"", key, escape(value)))
+                    .collect::<Vec<_>>();
+                let _ = write!(text, "{{{}}}", labels.join(","));
+            }
+            let _ = writeln!(text, " {}", m.value);
+        }
+    }
+    text
 }
 
-/// The root sink for all framework metrics.
-pub(crate) static SINK: Lazy<Sink> = Lazy::new(|| global().sink().scoped("capsule"));
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn value(measurements: &[Measurement], name: &str, port: &str, reason: Option<&str>) -> u64 {
+        measurements
+            .iter()
+            .find(|m| {
+                m.name == name
+                    && m.labels.contains(&("port", port.to_owned()))
+                    && m.labels.contains(&("core", "any".to_owned()))
+                    && reason.map_or(true, |r| m.labels.contains(&("reason", r.to_owned())))
+            })
+            .map(|m| m.value)
+            .unwrap()
+    }
+
+    #[test]
+    fn counters_totals() {
+        let counters = Counters::new();
+        counters.record(2, 128);
+        counters.record(1, 64);
+        counters.record_dropped(DropCause::RingFull, 3);
+
+        let totals = counters.totals();
+        assert_eq!(3, totals.packets);
+        assert_eq!(192, totals.octets);
+        assert_eq!(3, totals.dropped(DropCause::RingFull));
+        assert_eq!(0, totals.dropped(DropCause::NoMbuf));
+    }
+
+    #[test]
+    fn collect_sums_queues() {
+        let q0 = port_counters("metrics-test0", Direction::Tx);
+        let q1 = port_counters("metrics-test0", Direction::Tx);
+        q0.record(1, 60);
+        q1.record(2, 120);
+        q1.record_dropped(DropCause::TxQueueFull, 4);
+
+        let measurements = collect();
+        assert_eq!(3, value(&measurements, "port.packets", "metrics-test0", None));
+        assert_eq!(180, value(&measurements, "port.octets", "metrics-test0", None));
+        assert_eq!(
+            4,
+            value(&measurements, "port.dropped", "metrics-test0", Some("tx_queue_full"))
+        );
+
+        // the counters of a port that is gone are forgotten
+        drop(q0);
+        drop(q1);
+        assert!(collect()
+            .iter()
+            .all(|m| !m.labels.contains(&("port", "metrics-test0".to_owned()))));
+    }
+
+    #[test]
+    fn encode_text() {
+        let measurements = vec![
+            Measurement::counter(
+                "port.packets".to_owned(),
+                vec![("port", "eth0".to_owned()), ("dir", "rx".to_owned())],
+                3,
+            ),
+            Measurement::counter(
+                "port.packets".to_owned(),
+                vec![("port", "eth0".to_owned()), ("dir", "tx".to_owned())],
+                5,
+            ),
+            Measurement::gauge("mempool.free", vec![("mempool", "pool\"0".to_owned())], 7),
+            Measurement::gauge("fdb.entries", vec![], 2),
+        ];
+        assert_eq!(
+            "# TYPE port_packets counter\n\
+             port_packets{port=\"eth0\",dir=\"rx\"} 3\n\
+             port_packets{port=\"eth0\",dir=\"tx\"} 5\n\
+             # TYPE mempool_free gauge\n\
+             mempool_free{mempool=\"pool\\\"0\"} 7\n\
+             # TYPE fdb_entries gauge\n\
+             fdb_entries 2\n",
+            encode(&measurements)
+        );
+    }
+
+    #[test]
+    fn encode_collected() {
+        let rx = port_counters("metrics-test1", Direction::Rx);
+        let tx = port_counters("metrics-test1", Direction::Tx);
+        for counters in &[&rx, &tx] {
+            // as if counted on cores 0 and 1
+            counters.cores[0].packets.fetch_add(1, Ordering::Relaxed);
+            counters.cores[1].packets.fetch_add(2, Ordering::Relaxed);
+        }
+
+        let text = encode(&collect());
+        let lines = text.lines().collect::<Vec<_>>();
+        for family in &["port_packets", "port_octets", "port_dropped"] {
+            let header = format!("# TYPE {} counter", family);
+            let headers = lines.iter().filter(|line| **line == header).count();
+            assert_eq!(1, headers, "{}", text);
+
+            // the samples of a family follow its TYPE line
+            let start = lines.iter().position(|line| *line == header).unwrap();
+            let prefix = format!("{}{{", family);
+            let samples = lines
+                .iter()
+                .enumerate()
+                .filter(|(_, line)| line.starts_with(&prefix))
+                .map(|(idx, _)| idx)
+                .collect::<Vec<_>>();
+            assert_eq!(
+                (start + 1..start + 1 + samples.len()).collect::<Vec<_>>(),
+                samples,
+                "{}",
+                text
+            );
+        }
+
+        let ours = lines
+            .iter()
+            .filter(|line| line.starts_with("port_packets{port=\"metrics-test1\""))
+            .count();
+        assert_eq!(4, ours);
+    }
+}
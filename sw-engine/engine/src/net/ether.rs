@@ -3,9 +3,17 @@
  * Created by Ratnadeep Bhattacharya
  */
 
+use super::{fdb::UNTAGGED, mac::MacAddr};
 use crate::dpdk::Mbuf;
 use dpdk_ffi;
 
+/// Ether type of an 802.1Q tagged frame
+const ETHER_TYPE_VLAN: u16 = 0x8100;
+/// Offset of the VLAN tag control information
+const VLAN_TCI_OFFSET: usize = 14;
+/// The VLAN id is the lower 12 bits of the tag control information
+const VLAN_ID_MASK: u16 = 0x0fff;
+
 pub struct EtherHdr(dpdk_ffi::rte_ether_hdr);
 
 impl EtherHdr {
@@ -16,4 +24,26 @@ impl EtherHdr {
 	pub fn get(self) -> dpdk_ffi::rte_ether_hdr {
 		self.0
 	}
+
+	/// Returns the source MAC address of the frame
+	pub fn src_mac(&self) -> MacAddr {
+		self.0.s_addr.addr_bytes.into()
+	}
+
+	/// Returns the destination MAC address of the frame
+	pub fn dst_mac(&self) -> MacAddr {
+		self.0.d_addr.addr_bytes.into()
+	}
+
+	/// Returns the VLAN id of the frame in `buf`
+	/// Frames without an 802.1Q tag are on `UNTAGGED`
+	pub fn vlan_id(&self, buf: &Mbuf) -> u16 {
+		if u16::from_be(self.0.ether_type) != ETHER_TYPE_VLAN {
+			return UNTAGGED;
+		}
+		match buf.read_data::<[u8; 2]>(VLAN_TCI_OFFSET) {
+			Ok(tci) => u16::from_be_bytes(unsafe { *tci.as_ptr() }) & VLAN_ID_MASK,
+			Err(_) => UNTAGGED,
+		}
+	}
 }
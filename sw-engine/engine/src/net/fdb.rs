@@ -0,0 +1,286 @@
+/*
+ * Created on Sun Oct 18 2026:10:12:41
+ * Created by Ratnadeep Bhattacharya
+ */
+
+use super::mac::MacAddr;
+use crossbeam_utils::sync::ShardedLock; // faster reads than RWLock but slower writes
+use std::{
+	collections::HashMap,
+	fmt, result,
+	sync::atomic::{AtomicU64, Ordering},
+	time::{Duration, Instant},
+};
+
+/// VLAN id used for frames that carry no 802.1Q tag
+pub const UNTAGGED: u16 = 0;
+
+/// Where a MAC address lives: behind a physical port or inside a container
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Endpoint {
+	/// A physical port, by port id
+	Port(u16),
+	/// A container, by client id
+	Client(u16),
+}
+
+impl fmt::Display for Endpoint {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			Endpoint::Port(id) => write!(f, "port {}", id),
+			Endpoint::Client(id) => write!(f, "client {}", id),
+		}
+	}
+}
+
+/// How an entry got into the forwarding database
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Origin {
+	/// Added by the engine, e.g. when a container registers its MAC
+	/// Static entries never age out and are never moved by learning
+	Static,
+	/// Learnt from the source MAC of a frame
+	Dynamic,
+}
+
+/// A forwarding database entry
+struct FdbEntry {
+	endpoint: Endpoint,
+	origin: Origin,
+	// milliseconds since the database was created
+	// atomic so that a known MAC can be refreshed under the read lock
+	last_seen: AtomicU64,
+}
+
+/// The L2 forwarding database
+///
+/// Maps a (MAC, VLAN) pair to the port or client it was last seen on
+pub struct Fdb {
+	epoch: Instant,
+	table: ShardedLock<HashMap<(MacAddr, u16), FdbEntry>>,
+}
+
+impl Fdb {
+	/// Returns an empty forwarding database
+	pub fn new() -> Self {
+		Self {
+			epoch: Instant::now(),
+			table: ShardedLock::new(HashMap::new()),
+		}
+	}
+
+	/// Milliseconds elapsed since the database was created
+	fn now(&self) -> u64 {
+		self.epoch.elapsed().as_millis() as u64
+	}
+
+	/// Learn that `mac` on `vlan` was seen as the source of a frame from `endpoint`
+	pub fn learn(&self, mac: MacAddr, vlan: u16, endpoint: Endpoint) {
+		let now = self.now();
+
+		// NOTE: this runs for every packet, so the common case of a MAC that
+		// is already known on the same endpoint only takes the read lock
+		if let Ok(table) = self.table.read() {
+			if let Some(entry) = (*table).get(&(mac, vlan)) {
+				if entry.origin == Origin::Static {
+					return;
+				}
+				if entry.endpoint == endpoint {
+					entry.last_seen.store(now, Ordering::Relaxed);
+					return;
+				}
+			}
+		}
+
+		// new MAC, or the MAC moved to another endpoint
+		if let Ok(mut table) = self.table.write() {
+			// check again, the entry might have been made static meanwhile
+			match (*table).get(&(mac, vlan)) {
+				Some(entry) if entry.origin == Origin::Static => (),
+				_ => {
+					(*table).insert((mac, vlan), FdbEntry::new(endpoint, Origin::Dynamic, now));
+				}
+			}
+		}
+	}
+
+	/// Bind `mac` on `vlan` to `endpoint` permanently
+	/// An address bound to another endpoint stays where it is, that endpoint is returned
+	pub fn add_static(&self, mac: MacAddr, vlan: u16, endpoint: Endpoint) -> result::Result<(), Endpoint> {
+		let now = self.now();
+		if let Ok(mut table) = self.table.write() {
+			match (*table).get(&(mac, vlan)) {
+				Some(entry) if entry.origin == Origin::Static && entry.endpoint != endpoint => {
+					return Err(entry.endpoint);
+				}
+				_ => {
+					(*table).insert((mac, vlan), FdbEntry::new(endpoint, Origin::Static, now));
+				}
+			}
+		}
+		Ok(())
+	}
+
+	/// Returns the endpoint `mac` on `vlan` was last seen on
+	pub fn lookup(&self, mac: &MacAddr, vlan: u16) -> Option<Endpoint> {
+		match self.table.read() {
+			Ok(table) => (*table).get(&(*mac, vlan)).map(|entry| entry.endpoint),
+			_ => None,
+		}
+	}
+
+	/// Returns the origin of the entry for `mac` on `vlan`
+	pub fn origin(&self, mac: &MacAddr, vlan: u16) -> Option<Origin> {
+		match self.table.read() {
+			Ok(table) => (*table).get(&(*mac, vlan)).map(|entry| entry.origin),
+			_ => None,
+		}
+	}
+
+	/// Remove the entry for `mac` on `vlan`
+	pub fn remove(&self, mac: &MacAddr, vlan: u16) -> Option<Endpoint> {
+		match self.table.write() {
+			Ok(mut table) => (*table).remove(&(*mac, vlan)).map(|entry| entry.endpoint),
+			_ => None,
+		}
+	}
+
+	/// Remove every entry, static or dynamic, bound to `endpoint`
+	/// Returns the number of entries removed
+	pub fn purge(&self, endpoint: Endpoint) -> usize {
+		match self.table.write() {
+			Ok(mut table) => {
+				let before = (*table).len();
+				(*table).retain(|_, entry| entry.endpoint != endpoint);
+				before - (*table).len()
+			}
+			_ => 0,
+		}
+	}
+
+	/// Evict the dynamic entries that have not been seen for longer than `timeout`
+	/// Returns the number of entries evicted
+	pub fn age(&self, timeout: Duration) -> usize {
+		self.evict_idle(self.now(), timeout.as_millis() as u64)
+	}
+
+	fn evict_idle(&self, now: u64, timeout: u64) -> usize {
+		match self.table.write() {
+			Ok(mut table) => {
+				let before = (*table).len();
+				(*table).retain(|_, entry| {
+					entry.origin == Origin::Static
+						|| now.saturating_sub(entry.last_seen.load(Ordering::Relaxed)) <= timeout
+				});
+				before - (*table).len()
+			}
+			_ => 0,
+		}
+	}
+
+	/// Number of entries in the database
+	pub fn len(&self) -> usize {
+		match self.table.read() {
+			Ok(table) => (*table).len(),
+			_ => 0,
+		}
+	}
+
+	/// Whether the database is empty
+	pub fn is_empty(&self) -> bool {
+		self.len() == 0
+	}
+}
+
+impl Default for Fdb {
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
+impl FdbEntry {
+	fn new(endpoint: Endpoint, origin: Origin, now: u64) -> Self {
+		Self {
+			endpoint,
+			origin,
+			last_seen: AtomicU64::new(now),
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	const MAC: MacAddr = MacAddr::UNSPECIFIED;
+
+	#[test]
+	fn learn_and_move() {
+		let fdb = Fdb::new();
+		assert_eq!(None, fdb.lookup(&MAC, UNTAGGED));
+
+		fdb.learn(MAC, UNTAGGED, Endpoint::Port(0));
+		assert_eq!(Some(Endpoint::Port(0)), fdb.lookup(&MAC, UNTAGGED));
+		assert_eq!(Some(Origin::Dynamic), fdb.origin(&MAC, UNTAGGED));
+
+		// same MAC on another VLAN is a different entry
+		assert_eq!(None, fdb.lookup(&MAC, 10));
+
+		fdb.learn(MAC, UNTAGGED, Endpoint::Client(3));
+		assert_eq!(Some(Endpoint::Client(3)), fdb.lookup(&MAC, UNTAGGED));
+		assert_eq!(1, fdb.len());
+	}
+
+	#[test]
+	fn static_entries_do_not_move() {
+		let fdb = Fdb::new();
+		assert_eq!(Ok(()), fdb.add_static(MAC, UNTAGGED, Endpoint::Client(1)));
+		fdb.learn(MAC, UNTAGGED, Endpoint::Port(0));
+
+		assert_eq!(Some(Endpoint::Client(1)), fdb.lookup(&MAC, UNTAGGED));
+		assert_eq!(Some(Origin::Static), fdb.origin(&MAC, UNTAGGED));
+	}
+
+	#[test]
+	fn static_entries_are_not_taken_over() {
+		let fdb = Fdb::new();
+		// a dynamic entry gives way
+		fdb.learn(MAC, UNTAGGED, Endpoint::Port(0));
+		assert_eq!(Ok(()), fdb.add_static(MAC, UNTAGGED, Endpoint::Client(1)));
+		// the owner may bind it again, nobody else may
+		assert_eq!(Ok(()), fdb.add_static(MAC, UNTAGGED, Endpoint::Client(1)));
+		assert_eq!(Err(Endpoint::Client(1)), fdb.add_static(MAC, UNTAGGED, Endpoint::Client(2)));
+		assert_eq!(Some(Endpoint::Client(1)), fdb.lookup(&MAC, UNTAGGED));
+		// same MAC on another VLAN is free
+		assert_eq!(Ok(()), fdb.add_static(MAC, 10, Endpoint::Client(2)));
+
+		// free once the owner is purged
+		fdb.purge(Endpoint::Client(1));
+		assert_eq!(Ok(()), fdb.add_static(MAC, UNTAGGED, Endpoint::Client(2)));
+		assert_eq!(Some(Endpoint::Client(2)), fdb.lookup(&MAC, UNTAGGED));
+	}
+
+	#[test]
+	fn aging_evicts_idle_dynamic_entries() {
+		let fdb = Fdb::new();
+		let other = MacAddr::new(0, 0, 0, 0, 0, 1);
+		fdb.learn(MAC, UNTAGGED, Endpoint::Port(0));
+		assert_eq!(Ok(()), fdb.add_static(other, UNTAGGED, Endpoint::Client(1)));
+
+		assert_eq!(0, fdb.evict_idle(fdb.now(), 1_000));
+		assert_eq!(1, fdb.evict_idle(fdb.now() + 1_001, 1_000));
+		assert_eq!(None, fdb.lookup(&MAC, UNTAGGED));
+		assert_eq!(Some(Endpoint::Client(1)), fdb.lookup(&other, UNTAGGED));
+	}
+
+	#[test]
+	fn purge_endpoint() {
+		let fdb = Fdb::new();
+		fdb.learn(MAC, UNTAGGED, Endpoint::Client(1));
+		assert_eq!(Ok(()), fdb.add_static(MacAddr::new(0, 0, 0, 0, 0, 1), 10, Endpoint::Client(1)));
+		fdb.learn(MacAddr::new(0, 0, 0, 0, 0, 2), UNTAGGED, Endpoint::Port(0));
+
+		assert_eq!(2, fdb.purge(Endpoint::Client(1)));
+		assert_eq!(1, fdb.len());
+	}
+}
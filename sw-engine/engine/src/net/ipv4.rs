@@ -9,8 +9,14 @@ use dpdk_ffi;
 pub struct Ipv4Hdr(dpdk_ffi::rte_ipv4_hdr);
 
 impl Ipv4Hdr {
-	pub fn from_mbuf(buf: &mut Mbuf) -> Self {
-		Self(unsafe { *(dpdk_ffi::_pkt_ipv4_hdr(buf.raw_mut()) as *mut dpdk_ffi::rte_ipv4_hdr) })
+	/// Returns `None` if the packet is not IPv4
+	pub fn from_mbuf(buf: &mut Mbuf) -> Option<Self> {
+		let hdr = unsafe { dpdk_ffi::_pkt_ipv4_hdr(buf.raw_mut()) };
+		if hdr.is_null() {
+			None
+		} else {
+			Some(Self(unsafe { *hdr }))
+		}
 	}
 
 	pub fn get(self) -> dpdk_ffi::rte_ipv4_hdr {
@@ -31,6 +31,9 @@ impl MacAddr {
     /// A MAC address representing an unspecified address: 00:00:00:00:00:00.
     pub const UNSPECIFIED: Self = MacAddr([0, 0, 0, 0, 0, 0]);
 
+    /// The broadcast MAC address: ff:ff:ff:ff:ff:ff.
+    pub const BROADCAST: Self = MacAddr([0xff, 0xff, 0xff, 0xff, 0xff, 0xff]);
+
     /// Creates a MAC address from 6 octets.
     #[allow(clippy::many_single_char_names)]
     pub fn new(a: u8, b: u8, c: u8, d: u8, e: u8, f: u8) -> Self {
@@ -42,6 +45,20 @@ impl MacAddr {
     pub fn octets(&self) -> [u8; 6] {
         self.0
     }
+
+    /// Returns whether this is the broadcast address.
+    #[allow(clippy::trivially_copy_pass_by_ref)]
+    pub fn is_broadcast(&self) -> bool {
+        *self == Self::BROADCAST
+    }
+
+    /// Returns whether this is a group address, i.e. multicast or
+    /// broadcast. The individual/group bit is the least significant bit
+    /// of the first octet.
+    #[allow(clippy::trivially_copy_pass_by_ref)]
+    pub fn is_multicast(&self) -> bool {
+        self.0[0] & 0x01 != 0
+    }
 }
 
 impl fmt::Display for MacAddr {
@@ -120,4 +137,13 @@ mod tests {
             "12:34:56:ab:cd:ef".parse().unwrap()
         );
     }
+
+    #[test]
+    fn group_addresses() {
+        assert!(MacAddr::BROADCAST.is_broadcast());
+        assert!(MacAddr::BROADCAST.is_multicast());
+        assert!(MacAddr::new(0x01, 0x00, 0x5e, 0x00, 0x00, 0xfb).is_multicast());
+        assert!(!MacAddr::new(0x01, 0x00, 0x5e, 0x00, 0x00, 0xfb).is_broadcast());
+        assert!(!MacAddr::new(0x12, 0x34, 0x56, 0xAB, 0xCD, 0xEF).is_multicast());
+    }
 }
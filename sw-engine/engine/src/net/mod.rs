@@ -20,12 +20,14 @@
 
 mod cidr;
 mod ether;
+mod fdb;
 mod ipv4;
 mod mac;
 mod routing_table;
 
 pub use self::cidr::{Cidr, CidrError, Ipv4Cidr, Ipv6Cidr};
 pub use self::ether::EtherHdr;
+pub use self::fdb::{Endpoint, Fdb, Origin, UNTAGGED};
 pub use self::ipv4::Ipv4Hdr;
 pub use self::mac::{MacAddr, MacParseError};
 pub use self::routing_table::RoutingTable;
@@ -89,6 +91,14 @@ impl FiveTuple {
 		}
 	}
 
+	pub fn get_s_mac(&self) -> MacAddr {
+		self.src_mac
+	}
+
+	pub fn get_s_ip(&self) -> Ipv4Addr {
+		self.src_ip
+	}
+
 	pub fn get_d_mac(&self) -> MacAddr {
 		self.dst_mac
 	}
@@ -18,6 +18,14 @@ pub struct RoutingTable {
 }
 
 impl RoutingTable {
+	/// Returns an empty routing table
+	pub fn new() -> Self {
+		Self {
+			mac_table: Arc::new(ShardedLock::new(HashMap::new())),
+			ip_table: Arc::new(ShardedLock::new(HashMap::new())),
+		}
+	}
+
 	/// Add a mac and ip to the routing table
 	pub fn add(&self, mac: MacAddr, ip: Ipv4Addr) {
 		// NOTE: This is the only function that needs a write lock.
@@ -28,7 +36,8 @@ impl RoutingTable {
 			if let Ok(mut ip_table) = self.ip_table.write() {
 				// if either of the tables doesn't contain the ip or the mac
 				// we update both the tables
-				if !self.contains_mac(&mac) || !self.contains_ip(&ip) {
+				// NOTE: check through the write guards, taking a read lock here would deadlock
+				if !(*mac_table).contains_key(&mac) || !(*ip_table).contains_key(&ip) {
 					(*mac_table).insert(mac, ip);
 					(*ip_table).insert(ip, mac);
 				}
@@ -80,3 +89,9 @@ impl RoutingTable {
 		}
 	}
 }
+
+impl Default for RoutingTable {
+	fn default() -> Self {
+		Self::new()
+	}
+}
@@ -0,0 +1,99 @@
+/*
+ * Created on Sun Oct 18 2026:15:32:08
+ * Created by Ratnadeep Bhattacharya
+ */
+
+use std::collections::BTreeSet;
+
+/// Hands out the client ids
+///
+/// Ids of clients that stopped are handed out again, lowest first,
+/// before new ones are minted
+pub struct ClientIds {
+	next: u16,
+	free: BTreeSet<u16>,
+	exhausted: bool,
+}
+
+impl ClientIds {
+	/// Returns an allocator that starts at id 0
+	pub fn new() -> Self {
+		Self {
+			next: 0,
+			free: BTreeSet::new(),
+			exhausted: false,
+		}
+	}
+
+	/// Allocate an id, None if every id is in use
+	pub fn alloc(&mut self) -> Option<u16> {
+		if let Some(id) = self.free.iter().next().copied() {
+			self.free.remove(&id);
+			return Some(id);
+		}
+		if self.exhausted {
+			return None;
+		}
+		let id = self.next;
+		match self.next.checked_add(1) {
+			Some(next) => self.next = next,
+			None => self.exhausted = true,
+		}
+		Some(id)
+	}
+
+	/// Give `id` back so it can be handed out again
+	/// Returns false if the id was not in use
+	pub fn release(&mut self, id: u16) -> bool {
+		if !self.exhausted && id >= self.next {
+			return false;
+		}
+		self.free.insert(id)
+	}
+}
+
+impl Default for ClientIds {
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn unique_ids() {
+		let mut ids = ClientIds::new();
+		assert_eq!(Some(0), ids.alloc());
+		assert_eq!(Some(1), ids.alloc());
+		assert_eq!(Some(2), ids.alloc());
+	}
+
+	#[test]
+	fn recycle_ids() {
+		let mut ids = ClientIds::new();
+		for _ in 0..4 {
+			ids.alloc();
+		}
+		assert!(ids.release(2));
+		assert!(ids.release(1));
+		// released twice or never handed out
+		assert!(!ids.release(1));
+		assert!(!ids.release(7));
+
+		assert_eq!(Some(1), ids.alloc());
+		assert_eq!(Some(2), ids.alloc());
+		assert_eq!(Some(4), ids.alloc());
+	}
+
+	#[test]
+	fn exhaust_ids() {
+		let mut ids = ClientIds::new();
+		ids.next = u16::MAX;
+		assert_eq!(Some(u16::MAX), ids.alloc());
+		assert_eq!(None, ids.alloc());
+		assert!(ids.release(u16::MAX));
+		assert_eq!(Some(u16::MAX), ids.alloc());
+	}
+}
@@ -0,0 +1,276 @@
+/*
+ * Created on Sun Oct 18 2026:16:48:21
+ * Created by Ratnadeep Bhattacharya
+ */
+
+use crate::{
+	config::EngineConfig,
+	debug,
+	dockerlib::{Message, ProtocolError, Request, Response},
+	info,
+};
+use failure::{format_err, Fallible};
+use std::{
+	collections::HashMap,
+	fs::{self, DirBuilder, Permissions},
+	os::unix::fs::{DirBuilderExt, PermissionsExt},
+	path::Path,
+	result,
+	sync::Mutex,
+};
+
+/// The engine's end of the control channel
+///
+/// A ROUTER socket queues the requests of every client and tags each of
+/// them with the identity of its peer. Many registrations can be in flight
+/// at once and every answer is routed back to the client that asked.
+pub struct ControlPlane {
+	// zmq sockets must not be used from two threads at once
+	socket: Mutex<zmq::Socket>,
+	// routing envelope of each registered client, to reach it unprompted
+	peers: Mutex<HashMap<u16, Vec<Vec<u8>>>>,
+}
+
+impl ControlPlane {
+	/// Bind the control socket to the configured endpoint
+	///
+	/// The file of a Unix socket is given the configured permissions once
+	/// bound, so that only processes that can write to it can register rings.
+	/// Its directory is created owner and group only if it does not exist,
+	/// which keeps the others out until then.
+	pub fn bind(config: &EngineConfig) -> Fallible<Self> {
+		let context = zmq::Context::new();
+		let socket = context.socket(zmq::ROUTER)?;
+		let endpoint = &config.control_endpoint;
+		// sockets in the abstract namespace have no file
+		let path = match endpoint.strip_prefix("ipc://") {
+			Some(path) if !path.starts_with('@') => Some(Path::new(path)),
+			_ => None,
+		};
+		if let Some(dir) = path.and_then(Path::parent) {
+			if !dir.as_os_str().is_empty() && !dir.exists() {
+				DirBuilder::new().recursive(true).mode(0o770).create(dir)?;
+			}
+		}
+		socket.bind(endpoint)?;
+		if let Some(path) = path {
+			fs::set_permissions(path, Permissions::from_mode(config.control_socket_mode))?;
+		}
+		info!("control socket bound to {}", endpoint);
+		Ok(Self {
+			socket: Mutex::new(socket),
+			peers: Mutex::new(HashMap::new()),
+		})
+	}
+
+	/// Answer up to `max` of the requests already queued on the socket
+	/// Never blocks, returns the number of requests answered
+	///
+	/// `handler` turns each request into its response. Requests that can
+	/// not be parsed, and requests for a client registered by another peer,
+	/// are answered with an error without reaching it.
+	pub fn serve<F>(&self, max: usize, mut handler: F) -> Fallible<usize>
+	where
+		F: FnMut(Request) -> result::Result<Response, ProtocolError>,
+	{
+		let socket = match self.socket.lock() {
+			Ok(socket) => socket,
+			Err(_) => return Err(format_err!("control socket is poisoned")),
+		};
+		let mut served = 0;
+		while served < max {
+			let mut frames = match socket.recv_multipart(zmq::DONTWAIT) {
+				Ok(frames) => frames,
+				Err(zmq::Error::EAGAIN) => break, // no requests as of now
+				Err(err) => return Err(err.into()),
+			};
+			// the frames before the payload are the routing envelope: the
+			// peer identity and, from REQ style peers, an empty delimiter
+			let payload = match frames.pop() {
+				Some(payload) => payload,
+				None => continue,
+			};
+			let (request_id, result) = match std::str::from_utf8(&payload) {
+				Ok(data) => {
+					let (request_id, request) = Message::<Request>::parse(data);
+					let request = request.and_then(|request| self.check_sender(request, &frames));
+					(request_id, request.and_then(&mut handler))
+				}
+				Err(_) => (None, Err(ProtocolError::Malformed("message is not UTF-8".into()))),
+			};
+			let response = match result {
+				Ok(response) => {
+					if let Response::Registered { id, .. } = response {
+						self.remember(id, frames.clone());
+					}
+					response
+				}
+				Err(error) => {
+					debug!("rejected client request: {}", error);
+					Response::Error { error }
+				}
+			};
+			// the client matches the answer to its request by the id
+			frames.push(Message::with_request_id(request_id, response).to_json()?.into_bytes());
+			// a ROUTER socket drops the answer if the client went away
+			socket.send_multipart(frames, zmq::DONTWAIT)?;
+			served += 1;
+		}
+		Ok(served)
+	}
+
+	/// Only the peer that registered a client may act on its id
+	fn check_sender(&self, request: Request, envelope: &[Vec<u8>]) -> result::Result<Request, ProtocolError> {
+		let id = match request.client_id() {
+			Some(id) => id,
+			None => return Ok(request),
+		};
+		let owner = match self.peers.lock() {
+			Ok(peers) => peers.get(&id).map_or(false, |peer| peer.as_slice() == envelope),
+			Err(_) => return Err(ProtocolError::Internal("control peers are poisoned".into())),
+		};
+		if owner {
+			Ok(request)
+		} else {
+			Err(ProtocolError::UnknownClient(id))
+		}
+	}
+
+	/// Remember how to reach client `id`
+	fn remember(&self, id: u16, envelope: Vec<Vec<u8>>) {
+		if let Ok(mut peers) = self.peers.lock() {
+			peers.insert(id, envelope);
+		}
+	}
+
+	/// Forget client `id`, it is not told about anything anymore
+	pub fn forget(&self, id: u16) {
+		if let Ok(mut peers) = self.peers.lock() {
+			peers.remove(&id);
+		}
+	}
+
+	/// Send `notice` to every registered client without waiting for an answer
+	/// Returns the number of clients the notice was queued for
+	pub fn notify_all(&self, notice: Response) -> Fallible<usize> {
+		let payload = Message::new(notice).to_json()?.into_bytes();
+		let envelopes = match self.peers.lock() {
+			Ok(peers) => peers.values().cloned().collect::<Vec<_>>(),
+			Err(_) => return Err(format_err!("control peers are poisoned")),
+		};
+		let socket = match self.socket.lock() {
+			Ok(socket) => socket,
+			Err(_) => return Err(format_err!("control socket is poisoned")),
+		};
+		let mut notified = 0;
+		for mut frames in envelopes {
+			frames.push(payload.clone());
+			// a client that went away or does not keep up misses the notice
+			match socket.send_multipart(frames, zmq::DONTWAIT) {
+				Ok(()) => notified += 1,
+				Err(err) => debug!("failed to notify a client: {}", err),
+			}
+		}
+		Ok(notified)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::{
+		dockerlib::{ContainerInfo, RingRequest},
+		net::MacAddr,
+	};
+	use std::{net::Ipv4Addr, process, thread, time::Duration};
+
+	fn peer(endpoint: &str) -> zmq::Socket {
+		let socket = zmq::Context::new().socket(zmq::DEALER).unwrap();
+		socket.set_rcvtimeo(1000).unwrap();
+		socket.connect(endpoint).unwrap();
+		socket
+	}
+
+	type Handler<'a> = dyn FnMut(Request) -> result::Result<Response, ProtocolError> + 'a;
+
+	fn ask(plane: &ControlPlane, handler: &mut Handler<'_>, peer: &zmq::Socket, req: Request) -> Response {
+		let msg = Message::new(req).to_json().unwrap();
+		peer.send_multipart(&[&b""[..], msg.as_bytes()], 0).unwrap();
+		// the request may take a moment to reach the ROUTER
+		for _ in 0..100 {
+			if plane.serve(1, &mut *handler).unwrap() == 1 {
+				break;
+			}
+			thread::sleep(Duration::from_millis(10));
+		}
+		let mut frames = peer.recv_multipart(0).unwrap();
+		Message::<Response>::from_json(&String::from_utf8(frames.pop().unwrap()).unwrap()).unwrap()
+	}
+
+	#[test]
+	fn socket_file_gets_configured_mode() {
+		let path = format!("/tmp/sw-engine-control-mode-test-{}.sock", process::id());
+		let config = EngineConfig {
+			control_endpoint: format!("ipc://{}", path),
+			control_socket_mode: 0o600,
+			..EngineConfig::default()
+		};
+		let _plane = ControlPlane::bind(&config).unwrap();
+		let mode = fs::metadata(&path).unwrap().permissions().mode();
+		fs::remove_file(&path).ok();
+		assert_eq!(0o600, mode & 0o777);
+	}
+
+	#[test]
+	fn only_owner_acts_on_client() {
+		let path = format!("/tmp/sw-engine-control-test-{}.sock", process::id());
+		let endpoint = format!("ipc://{}", path);
+		let config = EngineConfig {
+			control_endpoint: endpoint.clone(),
+			..EngineConfig::default()
+		};
+		let plane = ControlPlane::bind(&config).unwrap();
+		let mut stopped = vec![];
+		let mut handler = |req: Request| match req {
+			Request::PodStarting { .. } => Ok(Response::Registered {
+				id: 0,
+				rx_ring: "RX-0".into(),
+				tx_ring: "TX-0".into(),
+			}),
+			Request::PodStopping { id } => {
+				stopped.push(id);
+				Ok(Response::Ok)
+			}
+			_ => Ok(Response::Ok),
+		};
+
+		let owner = peer(&endpoint);
+		let other = peer(&endpoint);
+		let container = ContainerInfo {
+			mac: MacAddr::new(0x02, 0, 0, 0, 0, 0x01),
+			ip: Ipv4Addr::new(10, 0, 0, 1),
+			vlan: 0,
+		};
+		let req = Request::PodStarting {
+			container,
+			rings: RingRequest::default(),
+		};
+		match ask(&plane, &mut handler, &owner, req) {
+			Response::Registered { id: 0, .. } => (),
+			other => panic!("unexpected {:?}", other),
+		}
+
+		assert_eq!(
+			Response::Error {
+				error: ProtocolError::UnknownClient(0)
+			},
+			ask(&plane, &mut handler, &other, Request::PodStopping { id: 0 })
+		);
+		assert_eq!(
+			Response::Ok,
+			ask(&plane, &mut handler, &owner, Request::PodStopping { id: 0 })
+		);
+		assert_eq!(vec![0], stopped);
+		fs::remove_file(path).ok();
+	}
+}
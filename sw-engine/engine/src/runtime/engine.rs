@@ -4,239 +4,1143 @@
  */
 
 use crate::{
-	dpdk::{Mbuf, EngineRingMap, Ring, RingType, SocketId, Channel}, info,
-	net::{EtherHdr, FiveTuple, Ipv4Hdr},
+	config::{RingConfig, RuntimeConfig},
+	dpdk::{self, Mbuf, EngineRingMap, LinkStatus, PortQueue, Ring, RingType, Channel, SocketId}, debug, info, warn,
+	net::{Endpoint, EtherHdr, Fdb, FiveTuple, Ipv4Hdr, MacAddr, RoutingTable},
+	runtime::{
+		self, handle_signals, shutdown_requested, teardown, ClientIds, ClientRegistry, ControlPlane, MetricsHandle,
+		MetricsServer, Placement, PortLinks, REGISTRY_SLOTS, Runtime, Teardown, Workers,
+	},
 	PortIdMbuf, FORWARDING_TABLE, PORTMAP,
-	PORTS, dockerlib::SOCKET, PACKET_READ_SIZE,
+	PORTS, PACKET_READ_SIZE,
+	dockerlib::{ContainerInfo, ProtocolError, Request, Response, RingRequest},
+	metrics::{self, Direction, DropCause, Measurement},
 };
-use serde_json;
-use crossbeam_queue::ArrayQueue;
-use std::{result, cell::Cell, time::Duration};
-use futures::{self, task::LocalSpawnExt};
-use failure::{Fallible, format_err};
+use crossbeam_queue::{ArrayQueue, SegQueue};
+use crossbeam_utils::sync::ShardedLock;
+use std::{result, cell::RefCell, collections::HashMap, net::SocketAddr, rc::Rc, sync::{mpsc, Arc, Mutex}, thread, time::{Duration, Instant}};
+use futures::{self, channel::oneshot, task::LocalSpawnExt};
+use failure::{Fail, Fallible};
 use async_std::task;
 use chashmap::CHashMap;
 
 /// Check for messages every 10 ms
 const TIMER_VAL: u64 = 10;
 
-#[derive(PartialEq)]
-enum ClientStatus {
+/// Answer at most 32 control requests at a time, not to starve the data path
+const CONTROL_BURST: usize = 32;
+
+/// Sweep the forwarding database for idle entries every second
+const AGING_SWEEP: u64 = 1;
+
+/// Look for clients that stopped sending heartbeats every second
+const REAPER_SWEEP: u64 = 1;
+
+/// Poll the links of the ports every second, for the devices without LSC interrupts
+const LINK_SWEEP: u64 = 1;
+
+/// Publish the client counters in the registry every second
+const COUNTERS_SWEEP: u64 = 1;
+
+/// Bursts a worker can hold between receiving and processing them
+const RX_BACKLOG: usize = 64;
+
+/// Lifecycle of a client as the engine sees it
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ClientStatus {
 	STARTING,
 	READY,
+	DEAD, // missed its heartbeats, reaped on the next sweep
+}
+
+/// Reasons for which a packet headed to a client is dropped
+#[derive(Debug, Fail)]
+pub enum DropReason {
+	/// The destination MAC is not bound to a client in the forwarding database
+	#[fail(display = "Unknown destination {}", _0)]
+	UnknownDestination(MacAddr),
+	/// The client is registered but has not reported READY
+	#[fail(display = "Client {} is not ready", _0)]
+	ClientNotReady(u16),
+	/// The client's RX ring has no room left
+	#[fail(display = "RX ring of client {} is full", _0)]
+	RingFull(u16),
+}
+
+/// The packets of a burst sorted by destination
+/// so that each destination is handled once per burst
+#[derive(Default)]
+struct Batches {
+	clients: HashMap<u16, Vec<Mbuf>>,
+	ports: HashMap<u16, Vec<Mbuf>>,
+}
+
+impl Batches {
+	fn push(&mut self, egress: Endpoint, pkt: Mbuf) {
+		let batch = match egress {
+			Endpoint::Client(id) => self.clients.entry(id),
+			Endpoint::Port(id) => self.ports.entry(id),
+		};
+		batch.or_insert_with(Vec::new).push(pkt);
+	}
+}
+
+/// The ring settings a client `asked` for on top of the engine's `defaults`
+/// Refused unless the rings can be created, on one of `sockets`
+fn ring_settings(
+	defaults: &RingConfig,
+	asked: &RingRequest,
+	sockets: &[SocketId],
+) -> result::Result<RingConfig, ProtocolError> {
+	let mut rings = defaults.clone();
+	if let Some(capacity) = asked.capacity {
+		Ring::validate_capacity(capacity).map_err(|_| ProtocolError::InvalidRingCapacity(capacity))?;
+		rings.capacity = capacity;
+	}
+	if let Some(socket) = asked.socket {
+		if !sockets.contains(&socket) {
+			return Err(ProtocolError::UnknownSocket(socket));
+		}
+		rings.socket = Some(socket);
+	}
+	rings.single_producer = asked.single_producer.unwrap_or(rings.single_producer);
+	rings.single_consumer = asked.single_consumer.unwrap_or(rings.single_consumer);
+	Ok(rings)
+}
+
+/// Where a frame on `vlan` that came in on `ingress` is flooded to
+///
+/// The `clients`, as pairs of id and VLAN, attached to `vlan` and every
+/// port in `ports`, which carry all the VLANs, except the ingress
+fn flood_targets(ingress: Endpoint, vlan: u16, clients: &[(u16, u16)], ports: &[u16]) -> Vec<Endpoint> {
+	clients
+		.iter()
+		.filter(|(_, client_vlan)| *client_vlan == vlan)
+		.map(|(id, _)| Endpoint::Client(*id))
+		.chain(ports.iter().copied().map(Endpoint::Port))
+		.filter(|target| *target != ingress)
+		.collect()
+}
+
+/// Bind the address of `container` to client `id`
+/// Refused while another client owns the same MAC on that VLAN
+fn claim_address(fdb: &Fdb, container: &ContainerInfo, id: u16) -> result::Result<(), ProtocolError> {
+	fdb.add_static(container.mac, container.vlan, Endpoint::Client(id)).map_err(|owner| {
+		warn!("client {} asked for {} on VLAN {}, owned by {:?}", id, container.mac, container.vlan, owner);
+		ProtocolError::AddressInUse(container.mac, container.vlan)
+	})
 }
 
 /// The engine that forms the core of the L2 forwarding plane
 pub struct Engine {
 	statusmap: CHashMap<u16, ClientStatus>, // maintain status of the clients
+	ready: ShardedLock<Arc<Vec<u16>>>, // the READY clients, rebuilt when a status changes
 	ringmap: EngineRingMap, // map for data plane Rings for clients registered
-	socket: zmq::Socket, // open a server socket to let clients register
-	new_msg: Cell<Option<(u16, u64)>>, // new message over the zmq socket
+	control: ControlPlane, // server socket to let clients register
+	fdb: Fdb, // where each (MAC, VLAN) was last seen
+	fdb_aging: Duration, // idle time after which a learnt MAC is forgotten
+	rings: RingConfig, // default settings of the client rings
+	ids: Mutex<ClientIds>, // hands out the client ids
+	last_seen: CHashMap<u16, Instant>, // when each client was last heard from
+	vlans: CHashMap<u16, u16>, // the VLAN each client is attached to
+	grace_period: Duration, // silence after which a client is considered dead
+	registry: ShardedLock<Option<ClientRegistry>>, // the clients as published in shared memory, freed at shutdown
+	links: PortLinks, // last known link status of each port
+	workers: usize, // number of packet processing workers
+	backlogs: CHashMap<u16, SegQueue<Vec<Mbuf>>>, // packets for a port handed to the workers that own its queues
+	shutdown_timeout: Duration, // time given to the packets in flight when stopping
 }
 
-type Result<Engine> = result::Result<Engine, zmq::Error>;
-
 impl Engine {
-	pub fn new() -> Result<Self> {
+	pub fn new(config: &RuntimeConfig) -> Fallible<Self> {
 		let statusmap = CHashMap::new();
 		let ringmap = EngineRingMap::new();
-		let context = zmq::Context::new();
-		match context.socket(zmq::REP) {
-			Ok(socket) => {
-				socket.connect(SOCKET)?; // return error if connection failed
-				Ok(Self { statusmap, ringmap, socket, new_msg: Cell::new(None) })
-			},
-			Err(err) => Err(err),
-		}
-	}
-
-	/// Get events from socket
-	fn get_msg(&self) -> Fallible<()> {
-		let timeout = 2; // timeout if there are no messages in 2 ms
-		match self.socket.poll(zmq::POLLIN, timeout) {
-			Ok(1) => {
-				let mut msg = zmq::Message::new();
-				self.socket.recv(&mut msg, zmq::DONTWAIT)?; // non-blocking receive
-				if let Some(smsg) = msg.as_str() {
-					let data: serde_json::Value = serde_json::from_str(smsg)?;
-					if let Some(id) = data["id"].as_u64() {
-						if let Some(m) = data["msg"].as_u64() {
-							// New message has been received and set in the engine
-							self.new_msg.set(Some((id as u16, m)));
-							Ok(())
-						}
-						else {
-							return Err(format_err!("Unknown Container Message"));
-						}
-					} else {
-							return Err(format_err!("Unknown ID Format"));
-						}
-				} else {
-					return Err(format_err!("Unknown Message Format"));
-				}
-			},
-			Ok(_) => Ok(()), // no messages as of now
-			Err(e) => return Err(format_err!("{}", e.to_string())),
+		let fdb = Fdb::new();
+		let fdb_aging = config.engine.fdb_aging_time;
+		let rings = config.engine.rings.clone();
+		// the forwarding table is shared with the rest of the crate
+		// the first engine to come up creates it
+		FORWARDING_TABLE.set(RoutingTable::new());
+		let control = ControlPlane::bind(&config.engine)?; // return error if binding failed
+		let registry = ClientRegistry::create(SocketId::ANY)?;
+		Ok(Self {
+			statusmap,
+			ready: ShardedLock::new(Arc::new(Vec::new())),
+			ringmap,
+			control,
+			fdb,
+			fdb_aging,
+			rings,
+			ids: Mutex::new(ClientIds::new()),
+			last_seen: CHashMap::new(),
+			vlans: CHashMap::new(),
+			grace_period: config.engine.client_grace_period,
+			registry: ShardedLock::new(Some(registry)),
+			links: PortLinks::new(),
+			workers: config.worker_cores().len().max(1),
+			backlogs: CHashMap::new(),
+			shutdown_timeout: config.engine.shutdown_timeout,
+		})
+	}
+
+	/// Ring settings of a registering client
+	fn client_rings(&self, asked: &RingRequest) -> result::Result<RingConfig, ProtocolError> {
+		ring_settings(&self.rings, asked, &SocketId::all())
+	}
+
+	/// Run `f` on the client registry, None once it is freed
+	fn with_registry<R>(&self, f: impl FnOnce(&ClientRegistry) -> R) -> Option<R> {
+		match self.registry.read() {
+			Ok(registry) => registry.as_ref().map(f),
+			Err(_) => None,
+		}
+	}
+
+	/// Allocate a client id
+	fn alloc_id(&self) -> result::Result<u16, ProtocolError> {
+		match self.ids.lock() {
+			Ok(mut ids) => ids.alloc().ok_or(ProtocolError::NoClientId),
+			Err(_) => Err(ProtocolError::Internal("client ids are poisoned".into())),
+		}
+	}
+
+	/// Give a client id back once the client is gone
+	fn release_id(&self, id: u16) {
+		if let Ok(mut ids) = self.ids.lock() {
+			ids.release(id);
 		}
 	}
 
+	/// Create the RX and TX rings of client `id`
+	fn create_channel(&self, id: u16, rings: &RingConfig) -> Fallible<Channel> {
+		let rx_q = Ring::new(
+				RingType::RX.ring_name(id),
+				rings.capacity,
+				rings.flags(),
+				rings.socket_id()
+				)?;
+		let tx_q = Ring::new(
+				RingType::TX.ring_name(id),
+				rings.capacity,
+				rings.flags(),
+				rings.socket_id()
+				)?;
+		Ok(Channel::new(id, tx_q, rx_q))
+	}
+
 	/// Register a new client or change its status
-	fn set_client_status(&self, id: u16, m: u64) -> Fallible<()> {
-		match m {
-			0 => {
-				let rx_q = Ring::new(
-						id as u16,
-						RingType::RX,
-						format!("RX-{}", id),
-						PACKET_READ_SIZE,
-						SocketId::current()
-						)?;
-				let tx_q = Ring::new(
-						id as u16,
-						RingType::TX,
-						format!("TX-{}", id),
-						PACKET_READ_SIZE,
-						SocketId::current()
-						)?;
-				// NOTE: if send fails then the client never gets ready
-				// send an error back
-				self.socket.send("1", 0)?;
-				// NOTE: no error means message was successfully sent
-				// still need to check if message was received successfully on client side
-				// but engine can move on
-				self.ringmap.ring_map.insert(id as u16, Channel { tx_q, rx_q});
-				self.statusmap.insert(id as u16, ClientStatus::STARTING);
+	fn set_client_status(&self, req: Request) -> result::Result<Response, ProtocolError> {
+		match req {
+			Request::PodStarting { container, rings } => {
+				let rings = self.client_rings(&rings)?;
+				let id = self.alloc_id()?;
+				// the container owns its MAC, learning must not move it elsewhere
+				if let Err(err) = claim_address(&self.fdb, &container, id) {
+					self.release_id(id);
+					return Err(err);
+				}
+				let channel = match self.create_channel(id, &rings) {
+					Ok(channel) => channel,
+					Err(err) => {
+						self.fdb.purge(Endpoint::Client(id));
+						self.release_id(id);
+						return Err(ProtocolError::Internal(err.to_string()));
+					}
+				};
+				let rx_ring = channel.rx_q.name().to_owned();
+				let tx_ring = channel.tx_q.name().to_owned();
+				if self.with_registry(|registry| registry.publish(id, &container, &rx_ring, &tx_ring)) != Some(true) {
+					debug!("client {} is beyond the registry, not published", id);
+				}
+				self.ringmap.ring_map.insert(id, channel);
+				self.statusmap.insert(id, ClientStatus::STARTING);
+				self.last_seen.insert(id, Instant::now());
+				self.vlans.insert(id, container.vlan);
+				FORWARDING_TABLE.get().add(container.mac, container.ip);
+				info!("client {} registered as {:?}", id, container);
+				return Ok(Response::Registered { id, rx_ring, tx_ring });
 			},
-			1 => {
-				let mut v = self.statusmap.get_mut(&(id as u16)).ok_or_else(|| format_err!("Failed to get client"))?;
-				*v = ClientStatus::READY;
+			Request::PodReady { id } => {
+				self.touch(id)?;
+				{
+					let mut v = self.statusmap.get_mut(&id).ok_or(ProtocolError::UnknownClient(id))?;
+					*v = ClientStatus::READY;
+				}
+				self.with_registry(|registry| registry.set_status(id, ClientStatus::READY));
+				self.refresh_ready();
 			},
-			2 => {
-				self.statusmap.remove(&(id as u16)).ok_or_else(|| format_err!("Failed to remove client"))?;
-				self.ringmap.ring_map.remove(&(id as u16)).ok_or_else(|| format_err!("Failed to remove client"))?;
+			Request::Heartbeat { id } => self.touch(id)?,
+			Request::PodStopping { id } => {
+				if !self.remove_client(id) {
+					return Err(ProtocolError::UnknownClient(id));
+				}
 			},
-			_ => return Err(format_err!("Unknown client status")),
 		}
-		Ok(())
+		Ok(Response::Ok)
+	}
+
+	/// Record that client `id` is alive
+	/// A client already marked dead stays dead
+	fn touch(&self, id: u16) -> result::Result<(), ProtocolError> {
+		match self.statusmap.get(&id) {
+			Some(status) if *status != ClientStatus::DEAD => {
+				self.last_seen.insert(id, Instant::now());
+				Ok(())
+			}
+			_ => Err(ProtocolError::UnknownClient(id)),
+		}
+	}
+
+	/// Forget client `id` and free everything it held
+	/// Returns false if there was no such client
+	fn remove_client(&self, id: u16) -> bool {
+		if self.statusmap.remove(&id).is_none() {
+			return false;
+		}
+		self.refresh_ready();
+		self.last_seen.remove(&id);
+		self.vlans.remove(&id);
+		if let Some(mut channel) = self.ringmap.ring_map.remove(&id) {
+			// the rings only hold pointers, give the packets back to the mempool
+			let drained = channel.drain();
+			debug!(cond: drained > 0, "freed {} packets left on the rings of client {}", drained, id);
+		}
+		self.fdb.purge(Endpoint::Client(id));
+		self.with_registry(|registry| registry.clear(id));
+		self.control.forget(id);
+		self.release_id(id);
+		true
+	}
+
+	/// Reap the clients that stopped sending heartbeats
+	///
+	/// A client silent for longer than the grace period is marked DEAD,
+	/// so no more packets are delivered to it, and freed on the next sweep
+	/// once nothing is in flight towards its rings anymore
+	async fn reap_clients(&self) {
+		loop {
+			task::sleep(Duration::from_secs(REAPER_SWEEP)).await;
+			for id in self.clients_with(ClientStatus::DEAD) {
+				self.remove_client(id);
+				info!("reaped dead client {}", id);
+			}
+			let mut died = false;
+			for id in self.silent_clients() {
+				if let Some(mut status) = self.statusmap.get_mut(&id) {
+					*status = ClientStatus::DEAD;
+					self.with_registry(|registry| registry.set_status(id, ClientStatus::DEAD));
+					warn!("client {} missed its heartbeats, marked dead", id);
+					died = true;
+				}
+			}
+			if died {
+				self.refresh_ready();
+			}
+		}
+	}
+
+	/// Ids of the clients not heard from for longer than the grace period
+	fn silent_clients(&self) -> Vec<u16> {
+		let ids = RefCell::new(Vec::new());
+		let grace_period = self.grace_period;
+		// CHashMap has no borrowing iterator, walk it with a retain that keeps everything
+		self.last_seen.retain(|id, seen| {
+			if seen.elapsed() > grace_period {
+				ids.borrow_mut().push(*id);
+			}
+			true
+		});
+		ids.into_inner()
 	}
 
 	/// Check for messages
 	/// set client status if there are messages
 	/// every TIMER_VAL period
+	///
+	/// At most CONTROL_BURST requests are answered per period,
+	/// the others stay queued on the socket for the next one
 	async fn check_n_set_client_status(&self) -> () {
-		task::sleep(Duration::from_micros(TIMER_VAL)).await;
-		if let Some(val) = self.new_msg.get() {
-			self.set_client_status(val.0, val.1).ok(); // NOTE: discards the error
-			self.new_msg.set(None);
+		loop {
+			task::sleep(Duration::from_millis(TIMER_VAL)).await;
+			if let Err(err) = self.control.serve(CONTROL_BURST, |req| self.set_client_status(req)) {
+				debug!("control socket: {}", err);
+			}
+		}
+	}
+
+	/// Send packets to a client
+	/// Returns the packets the client had no room for
+	fn send(&self, key: u16, pkts: Vec<Mbuf>) -> Fallible<Vec<Mbuf>> {
+		self.ringmap.send_burst(key, pkts)
+	}
+
+	/// Receive up to `max` packets from a client
+	fn receive(&self, key: u16, max: usize) -> Fallible<Vec<Mbuf>> {
+		self.ringmap.receive_burst(key, max)
+	}
+
+	/// Ids of the clients that are READY
+	///
+	/// A snapshot kept for the workers, which look at it for every burst
+	fn ready_clients(&self) -> Arc<Vec<u16>> {
+		match self.ready.read() {
+			Ok(ready) => Arc::clone(&ready),
+			Err(_) => Arc::new(Vec::new()),
 		}
-		()
 	}
 
-	/// Send packet to a client
-	fn send(&self, key: u16, pkt: Mbuf) -> Fallible<()> {
-		self.ringmap.send(key, pkt)
+	/// Rebuild the snapshot of the READY clients after a status change
+	/// Must not be called while holding an entry of `statusmap`
+	fn refresh_ready(&self) {
+		let ready = Arc::new(self.clients_with(ClientStatus::READY));
+		if let Ok(mut snapshot) = self.ready.write() {
+			*snapshot = ready;
+		}
+	}
+
+	/// Ids of the clients in status `wanted`
+	fn clients_with(&self, wanted: ClientStatus) -> Vec<u16> {
+		let ids = RefCell::new(Vec::new());
+		// CHashMap has no borrowing iterator, walk it with a retain that keeps everything
+		self.statusmap.retain(|id, status| {
+			if *status == wanted {
+				ids.borrow_mut().push(*id);
+			}
+			true
+		});
+		ids.into_inner()
 	}
 
-	/// Receive a packet from a client
-	fn receive(&self, key: u16, pkt: &mut Mbuf) -> Fallible<()> {
-		self.ringmap.receive(key, pkt)
+	/// Ids of the physical ports, a port with several queues is listed once
+	fn port_ids(&self) -> Vec<u16> {
+		let mut ids = PORTS.get().iter().map(PortQueue::get_portid).collect::<Vec<_>>();
+		ids.sort();
+		ids.dedup();
+		ids
 	}
 
 	/// Packet receive function
-	/// Receive packets from the NIC and place them in the deque mbufs
+	/// Receive packets from the port queues of the worker and place them in the deque mbufs
+	///
+	/// Returns the deadline to drain the packets in flight by, once told to stop
 	async fn rx_main(
 		&self, 
-		mut receiver: futures::channel::oneshot::Receiver<()>,
-		mbufs: &ArrayQueue<PortIdMbuf>,
-	) -> () {
-		let ports = PORTS.get();
-
+		mut receiver: oneshot::Receiver<Instant>,
+		mbufs: Rc<ArrayQueue<PortIdMbuf>>,
+	) -> Instant {
 		loop {
 			match receiver.try_recv() {
-				Ok(_) => break, // if we receive anything over the channel then stop receiving
-				Err(futures::channel::oneshot::Canceled) => (),
+				Ok(Some(deadline)) => return deadline, // stop receiving when told to over the channel
+				Ok(None) | Err(oneshot::Canceled) => (),
 			}
 			// let mut deque = VecDeque::new();
-			while !mbufs.is_full() {
-				for port in ports {
-					let recvd = port.receive(); // receive a batch of 32 packets from
-								// push to the end of the VecDeque, pop will happen from the front
-					match mbufs.push(PortIdMbuf {
-						portid: port.get_portid(),
-						buf: recvd,
-					}) {
-						Ok(()) => {}
-						Err(_) => info!("Failed to push pkt"),
-					};
-				}
+			if !mbufs.is_full() {
+				runtime::with_local_queues(|ports| {
+					for port in ports {
+						let recvd = port.receive(); // receive a batch of 32 packets from
+									// push to the end of the VecDeque, pop will happen from the front
+						match mbufs.push(PortIdMbuf {
+							portid: port.get_portid(),
+							buf: recvd,
+						}) {
+							Ok(()) => {}
+							Err(_) => info!("Failed to push pkt"),
+						};
+					}
+				});
 			}
+			// let the processing and transmit tasks run
+			task::yield_now().await;
 		}
-		()
 	}
 
 	/// Consumes the packets placed in the deque by rx_main
-	/// Extract the five tuple from each mbuf
+	/// Extract the five tuple from each IPv4 mbuf
 	/// Associate the five tuple with a port id
 	/// Update the routing table
-	async fn process_packets(&self, mbufs: &ArrayQueue<PortIdMbuf>) {
-		while !mbufs.is_empty() {
-			match mbufs.pop() {
-				Some(elem) => {
-					let pnum = elem.portid;
-					let mbufs = elem.buf;
-					if !mbufs.is_empty() {
-						for mut mbuf in mbufs {
-							let ether_hdr = EtherHdr::from_mbuf(&mut mbuf);
-							let ipv4_hdr = Ipv4Hdr::from_mbuf(&mut mbuf);
-							let five_tuple = FiveTuple::new(ipv4_hdr, ether_hdr);
-							let d_mac = five_tuple.get_d_mac();
-							let d_ip = five_tuple.get_d_ip();
-							// Add five tuple to the portmap
-							let portmap = PORTMAP.get();
-							portmap.insert(pnum, five_tuple);
-
-							// update forwarding table
-							let forwarding_table = FORWARDING_TABLE.get();
-							forwarding_table.add(d_mac, d_ip);
-
-							// process the packet
-							self.tx_to_clients(mbuf);
-						}
-					}
+	async fn process_packets(&self, mbufs: Rc<ArrayQueue<PortIdMbuf>>) {
+		loop {
+			while !mbufs.is_empty() {
+				match mbufs.pop() {
+					Some(elem) => self.process_burst(elem),
+					None => 
+						info!("Since we check that the array is non-empty before popping an mbuf, this branch should not have run!")
+				};
+			}
+			// nothing left to process, let rx_main fill the queue
+			task::yield_now().await;
+		}
+	}
+
+	/// Switch a burst of packets received on a port
+	fn process_burst(&self, elem: PortIdMbuf) {
+		let pnum = elem.portid;
+		let mbufs = elem.buf;
+		if !mbufs.is_empty() {
+			let ingress = Endpoint::Port(pnum);
+			let mut batches = Batches::default();
+			for mut mbuf in mbufs {
+				if let Some(ipv4_hdr) = Ipv4Hdr::from_mbuf(&mut mbuf) {
+					let ether_hdr = EtherHdr::from_mbuf(&mut mbuf);
+					let five_tuple = FiveTuple::new(ipv4_hdr, ether_hdr);
+					let s_mac = five_tuple.get_s_mac();
+					let s_ip = five_tuple.get_s_ip();
+					// Add five tuple to the portmap
+					let portmap = PORTMAP.get();
+					portmap.insert(pnum, five_tuple);
+
+					// update forwarding table
+					let forwarding_table = FORWARDING_TABLE.get();
+					forwarding_table.add(s_mac, s_ip);
 				}
-				None => 
-					info!("Since we check that the array is non-empty before popping an mbuf, this branch should not have run!")
-			};
+
+				// process the packet
+				self.switch(ingress, mbuf, &mut batches);
+			}
+			self.flush(ingress, batches);
+		}
+	}
+
+	/// Packet transmit function
+	/// Drain the TX rings of the READY clients in bursts
+	/// and switch the packets to their destination
+	///
+	/// Each client is drained by a single worker, `worker` takes the clients
+	/// whose id falls to it. The packets other workers handed over for the
+	/// ports of `worker` go out too
+	async fn tx_main(&self, worker: usize) {
+		loop {
+			self.collect_clients(worker);
+			self.drain_backlogs();
+			// let rx_main and process_packets run
+			task::yield_now().await;
+		}
+	}
+
+	/// Switch a burst from the TX ring of each READY client of `worker`
+	/// Returns the number of packets collected
+	fn collect_clients(&self, worker: usize) -> usize {
+		let mut collected = 0;
+		for &id in self.ready_clients().iter() {
+			if id as usize % self.workers != worker {
+				continue;
+			}
+			match self.receive(id, PACKET_READ_SIZE) {
+				Ok(pkts) if !pkts.is_empty() => {
+					collected += pkts.len();
+					self.tx_from_client(id, pkts);
+				}
+				_ => (), // nothing on this client's ring
+			}
+		}
+		collected
+	}
+
+	/// Switch a burst of packets sent by client `src`
+	/// Frames for a container on this host are hairpinned onto its RX ring
+	/// without touching the NIC, everything else leaves through a port
+	fn tx_from_client(&self, src: u16, pkts: Vec<Mbuf>) {
+		let ingress = Endpoint::Client(src);
+		let mut batches = Batches::default();
+		for pkt in pkts {
+			self.switch(ingress, pkt, &mut batches);
+		}
+		self.flush(ingress, batches);
+	}
+
+	/// Decide where a packet that came in on `ingress` goes
+	///
+	/// The source MAC is learnt against `ingress`. Group frames and frames for
+	/// unknown destinations are flooded, the rest is batched for the endpoint
+	/// the forwarding database has for the destination MAC
+	fn switch(&self, ingress: Endpoint, mut pkt: Mbuf, batches: &mut Batches) {
+		let ether_hdr = EtherHdr::from_mbuf(&mut pkt);
+		let vlan = ether_hdr.vlan_id(&pkt);
+		let d_mac = ether_hdr.dst_mac();
+		// learn where the sender lives
+		self.fdb.learn(ether_hdr.src_mac(), vlan, ingress);
+
+		// broadcast is a group address too
+		if d_mac.is_multicast() {
+			self.flood(ingress, vlan, pkt, batches);
+			return;
+		}
+		match self.fdb.lookup(&d_mac, vlan) {
+			Some(egress) if egress == ingress => {
+				debug!("dropped packet from {}: {} is on the same side", ingress, d_mac)
+			}
+			Some(egress) => batches.push(egress, pkt),
+			None => {
+				if !self.flood(ingress, vlan, pkt, batches) {
+					self.record_dropped(ingress, false, DropCause::UnknownDst, 1);
+					debug!(
+						"dropped packet from {}: {}",
+						ingress,
+						DropReason::UnknownDestination(d_mac)
+					);
+				}
+			}
+		}
+	}
+
+	/// Replicate a packet on `vlan` to every READY client attached to it
+	/// and every port except `ingress`
+	/// The copies are zero-copy clones sharing the packet data
+	/// Returns false if there was no one to flood to and the packet was dropped
+	fn flood(&self, ingress: Endpoint, vlan: u16, pkt: Mbuf, batches: &mut Batches) -> bool {
+		let clients = self
+			.ready_clients()
+			.iter()
+			.filter_map(|&id| self.vlans.get(&id).map(|client_vlan| (id, *client_vlan)))
+			.collect::<Vec<_>>();
+		let mut targets = flood_targets(ingress, vlan, &clients, &self.links.up(self.port_ids()));
+		// the last target gets the original handle
+		let last = match targets.pop() {
+			Some(target) => target,
+			None => return false,
+		};
+		for target in targets {
+			match pkt.clone_shallow() {
+				Ok(clone) => batches.push(target, clone),
+				Err(err) => {
+					self.record_dropped(target, true, DropCause::NoMbuf, 1);
+					debug!("dropped flooded packet for {}: {}", target, err)
+				}
+			}
+		}
+		batches.push(last, pkt);
+		true
+	}
+
+	/// Deliver the batched packets of a burst that came in on `ingress`
+	fn flush(&self, ingress: Endpoint, batches: Batches) {
+		for (id, pkts) in batches.clients {
+			self.tx_to_clients(ingress, id, pkts);
+		}
+		for (id, pkts) in batches.ports {
+			self.tx_to_port(id, pkts);
 		}
 	}
 
 	/// Transmit packets to L3 containers through DPDK rings
-	/// Consumes the buffer
-	fn tx_to_clients(&self, mut mbuf: Mbuf) {
-		unimplemented!();
+	/// Consumes the buffers
+	///
+	/// The packets go onto the RX ring of client `dst`.
+	/// Packets that can not be delivered are freed and the reason logged.
+	fn tx_to_clients(&self, ingress: Endpoint, dst: u16, pkts: Vec<Mbuf>) {
+		let ready = self
+			.statusmap
+			.get(&dst)
+			.map_or(false, |status| *status == ClientStatus::READY);
+		if !ready {
+			self.ringmap.record_dropped(dst, Direction::Rx, DropCause::NotReady, pkts.len());
+			debug!(
+				"dropped {} packets from {}: {}",
+				pkts.len(),
+				ingress,
+				DropReason::ClientNotReady(dst)
+			);
+			return;
+		}
+
+		let count = pkts.len();
+		match self.send(dst, pkts) {
+			Ok(unsent) if !unsent.is_empty() => {
+				self.ringmap.record_dropped(dst, Direction::Rx, DropCause::RingFull, unsent.len());
+				debug!(
+					"dropped {} packets from {}: {}",
+					unsent.len(),
+					ingress,
+					DropReason::RingFull(dst)
+				);
+				Mbuf::free_bulk(unsent);
+			}
+			Ok(_) => (),
+			// the channel went away after the status check
+			Err(_) => {
+				debug!(
+					"dropped {} packets from {}: {}",
+					count,
+					ingress,
+					DropReason::ClientNotReady(dst)
+				)
+			}
+		}
+	}
+
+	/// Record packets dropped on their way to (`towards`) or from `endpoint`
+	///
+	/// Directions are those of the endpoint's counters: packets towards a
+	/// port are TX, packets towards a client are RX
+	fn record_dropped(&self, endpoint: Endpoint, towards: bool, cause: DropCause, packets: usize) {
+		match endpoint {
+			Endpoint::Port(id) => {
+				let dir = if towards { Direction::Tx } else { Direction::Rx };
+				if let Some(port) = PORTS.get().iter().find(|port| port.get_portid() == id) {
+					port.record_dropped(dir, cause, packets);
+				}
+			}
+			Endpoint::Client(id) => {
+				let dir = if towards { Direction::Rx } else { Direction::Tx };
+				self.ringmap.record_dropped(id, dir, cause, packets);
+			}
+		}
+	}
+
+	/// Transmit packets out of port `port_id`
+	///
+	/// A worker only transmits through its own queues. Packets for a port it
+	/// has no queue on are handed to the workers that do
+	fn tx_to_port(&self, port_id: u16, pkts: Vec<Mbuf>) {
+		if !self.link_up(port_id) {
+			self.record_dropped(Endpoint::Port(port_id), true, DropCause::LinkDown, pkts.len());
+			debug!("link of port {} is down, dropped {} packets", port_id, pkts.len());
+			return;
+		}
+		let unsent = runtime::with_local_queues(|ports| {
+			match ports.iter().find(|port| port.get_portid() == port_id) {
+				Some(port) => {
+					port.transmit(pkts);
+					None
+				}
+				None => Some(pkts),
+			}
+		});
+		if let Some(pkts) = unsent {
+			match self.backlogs.get(&port_id) {
+				Some(backlog) => backlog.push(pkts),
+				None => debug!("port {} is gone, dropped {} packets", port_id, pkts.len()),
+			}
+		}
+	}
+
+	/// Transmit the packets handed over for the ports of the current worker
+	/// Returns the number of packets transmitted
+	fn drain_backlogs(&self) -> usize {
+		runtime::with_local_queues(|ports| {
+			let mut drained = 0;
+			for port in ports {
+				if let Some(backlog) = self.backlogs.get(&port.get_portid()) {
+					while let Some(pkts) = backlog.pop() {
+						drained += pkts.len();
+						port.transmit(pkts);
+					}
+				}
+			}
+			drained
+		})
+	}
+
+	/// Forward what is in flight on the current worker once RX stopped
+	///
+	/// The bursts received and not processed yet, the TX rings of the
+	/// clients of `worker` and the packets handed over for its ports, until
+	/// a round moves nothing or `deadline` passes
+	fn drain(&self, mbufs: &ArrayQueue<PortIdMbuf>, worker: usize, deadline: Instant) {
+		loop {
+			let mut moved = 0;
+			while let Some(elem) = mbufs.pop() {
+				moved += elem.buf.len();
+				self.process_burst(elem);
+			}
+			moved += self.collect_clients(worker);
+			moved += self.drain_backlogs();
+			if moved == 0 {
+				break;
+			}
+			if Instant::now() >= deadline {
+				warn!("worker {} ran out of time, freeing the packets in flight", worker);
+				break;
+			}
+		}
+	}
+
+	/// Current value of every engine metric
+	///
+	/// The port and client counters, the occupancy of the mempools and the
+	/// size of the forwarding database
+	pub fn metrics(&self) -> Vec<Measurement> {
+		let mut measurements = metrics::collect();
+		for pool in dpdk::mempool_stats() {
+			let labels = vec![("mempool", pool.name)];
+			measurements.push(Measurement::gauge("mempool.used", labels.clone(), pool.used as u64));
+			measurements.push(Measurement::gauge("mempool.free", labels, pool.free as u64));
+		}
+		measurements.push(Measurement::gauge("fdb.entries", vec![], self.fdb.len() as u64));
+		measurements.extend(self.links.measurements(self.port_ids()));
+		measurements
+	}
+
+	/// Serve the engine metrics on `addr` for Prometheus to scrape
+	pub fn serve_metrics(&'static self, addr: SocketAddr) -> Fallible<MetricsHandle> {
+		MetricsServer::bind(addr)?.spawn(move || self.metrics())
+	}
+
+	/// Whether the link of port `port_id` is up
+	/// A port whose link was never seen is taken to be up
+	fn link_up(&self, port_id: u16) -> bool {
+		self.links.is_up(port_id)
+	}
+
+	/// Record the link status of port `port_id`, logging the changes
+	fn link_changed(&self, port_id: u16, status: LinkStatus) {
+		if self.links.update(port_id, status) {
+			if status.up {
+				info!("link of port {} is {}", port_id, status);
+			} else {
+				warn!("link of port {} is down, no more flooding to it", port_id);
+			}
+		}
+	}
+
+	/// Follow the links of the ports
+	///
+	/// The LSC interrupts are picked up as they come. The devices that do
+	/// not raise them are polled
+	async fn watch_links(&self) {
+		let mut last_poll: Option<Instant> = None;
+		loop {
+			for event in dpdk::link_events() {
+				self.link_changed(event.port_id, event.status);
+			}
+			if last_poll.map_or(true, |at| at.elapsed() >= Duration::from_secs(LINK_SWEEP)) {
+				// a port with several queues has the same link on each
+				for port in PORTS.get() {
+					self.link_changed(port.get_portid(), port.link_status());
+				}
+				last_poll = Some(Instant::now());
+			}
+			task::sleep(Duration::from_millis(TIMER_VAL)).await;
+		}
+	}
+
+	/// Publish the counters of the clients' channels in the registry
+	async fn publish_counters(&self) {
+		loop {
+			task::sleep(Duration::from_secs(COUNTERS_SWEEP)).await;
+			// the clients beyond the registry are not published
+			for id in 0..REGISTRY_SLOTS as u16 {
+				if let Some(channel) = self.ringmap.ring_map.get(&id) {
+					let rx = channel.totals(Direction::Rx);
+					let tx = channel.totals(Direction::Tx);
+					self.with_registry(|registry| registry.publish_counters(id, &rx, &tx));
+				}
+			}
+		}
+	}
+
+	/// Evict idle MACs from the forwarding database
+	async fn age_fdb(&self) {
+		loop {
+			task::sleep(Duration::from_secs(AGING_SWEEP)).await;
+			let aged = self.fdb.age(self.fdb_aging);
+			debug!(cond: aged > 0, "aged out {} forwarding database entries", aged);
+		}
+	}
+
+	/// Spawn one worker per worker core of `runtime`
+	///
+	/// Each worker is pinned to its core, allocates from the mempool of its
+	/// socket and owns the port queues set up for its core. The master core
+	/// is left to the control plane, see `run_control`
+	///
+	/// # Errors
+	///
+	/// If a worker can not be pinned to its core or find a mempool on its
+	/// socket, the startup fails with `WorkerError::NotStarted`
+	pub fn spawn_workers(&'static self, runtime: &Runtime) -> Fallible<Workers> {
+		let placements = Placement::all(runtime);
+		for port_id in placements.iter().flat_map(Placement::port_ids) {
+			if !self.backlogs.contains_key(&port_id) {
+				self.backlogs.insert(port_id, SegQueue::new());
+			}
+		}
+
+		let mut workers = Workers::default();
+		for placement in placements {
+			let (stop, receiver) = oneshot::channel();
+			let (started, reports) = mpsc::channel();
+			let core = placement.core;
+			let thread = thread::Builder::new()
+				.name(format!("worker-{}", core.raw()))
+				.spawn(move || {
+					let worker = placement.index;
+					match placement.enter() {
+						Ok(()) => {
+							let _ = started.send(Ok(()));
+							self.work_horse(receiver, worker)
+						}
+						// the spawner fails the startup with it
+						Err(err) => {
+							let _ = started.send(Err(err));
+							Ok(())
+						}
+					}
+				})?;
+			workers.push(core, stop, reports, thread);
+		}
+		// on failure the workers already running are stopped as they are dropped
+		workers.wait_started()?;
+		info!("spawned {} workers", workers.len());
+		Ok(workers)
 	}
 
-	/// Function for each thread to run
-	pub fn work_horse(
-		&'static self,
-		receiver: futures::channel::oneshot::Receiver<()>,
-		mbufs: &'static ArrayQueue<PortIdMbuf>,
-	) -> Fallible<()> {
+	/// Function for each worker to run
+	fn work_horse(&'static self, receiver: oneshot::Receiver<Instant>, worker: usize) -> Fallible<()> {
 		// create an executor to run on the local thread only
 		let mut executor = futures::executor::LocalPool::new();
 		// a task spawner associated to the executor
 		let spawner = executor.spawner();
+		// the bursts received and not processed yet
+		let mbufs = Rc::new(ArrayQueue::new(RX_BACKLOG));
 		// create the required futures (green threads)
-		let reg_fut = self.check_n_set_client_status(); // check for new clients
-		let rx_fut = self.rx_main(receiver, mbufs); // get packets
-		let process_pkts_fut = self.process_packets(mbufs); // process packets
+		let rx_fut = self.rx_main(receiver, mbufs.clone()); // get packets
+		let process_pkts_fut = self.process_packets(mbufs.clone()); // process packets
+		let tx_fut = self.tx_main(worker); // send packets from the clients
 		// spawn the futures
 		let rx_fut_handle = spawner.spawn_local_with_handle(rx_fut)?;
 		spawner.spawn_local(process_pkts_fut)?;
-		spawner.spawn_local(reg_fut)?;
+		spawner.spawn_local(tx_fut)?;
 		// run the executor till rx_fut returns
-		// drop everything the moment the rx_main function returns
-		executor.run_until(rx_fut_handle);
+		let deadline = executor.run_until(rx_fut_handle);
+		// forward what was already received, the rest is freed with the queue
+		self.drain(&mbufs, worker, deadline);
+		Ok(())
+	}
+
+	/// Run the control plane on the current thread, the master core,
+	/// until the engine is asked to stop
+	///
+	/// Answers the clients, ages the forwarding database, reaps the dead
+	/// clients, follows the links of the ports and publishes the client
+	/// counters
+	pub fn run_control(&'static self) -> Fallible<()> {
+		let mut executor = futures::executor::LocalPool::new();
+		let spawner = executor.spawner();
+		let reg_fut = self.check_n_set_client_status(); // check for new clients
+		let aging_fut = self.age_fdb(); // forget idle MACs
+		let reaper_fut = self.reap_clients(); // free the clients that died
+		let links_fut = self.watch_links(); // follow the links of the ports
+		let counters_fut = self.publish_counters(); // refresh the client counters in the registry
+		spawner.spawn_local(reg_fut)?;
+		spawner.spawn_local(aging_fut)?;
+		spawner.spawn_local(reaper_fut)?;
+		spawner.spawn_local(links_fut)?;
+		spawner.spawn_local(counters_fut)?;
+		executor.run_until(self.wait_for_shutdown());
 		Ok(())
 	}
-}
\ No newline at end of file
+
+	/// Wait for a signal, or anyone else, to ask the engine to stop
+	async fn wait_for_shutdown(&self) {
+		while !shutdown_requested() {
+			task::sleep(Duration::from_millis(TIMER_VAL)).await;
+		}
+	}
+
+	/// Run the engine on `runtime` until SIGINT or SIGTERM
+	///
+	/// The workers run on their cores and the control plane on the calling
+	/// thread, the master core. Once asked to stop, the engine shuts down
+	/// gracefully and the runtime is torn down last
+	pub fn run(&'static self, runtime: Runtime) -> Fallible<()> {
+		handle_signals()?;
+		for port in runtime.ports() {
+			self.links.set_name(port.id().raw(), port.name());
+		}
+		let exporter = match runtime.config().metrics_address {
+			Some(addr) => Some(self.serve_metrics(addr)?),
+			None => None,
+		};
+		let workers = self.spawn_workers(&runtime)?;
+		self.run_control()?;
+		self.shutdown(Stopping {
+			engine: self,
+			workers: Some(workers),
+			exporter,
+			runtime: Some(runtime),
+		})
+	}
+
+	/// Stop the engine within the shutdown timeout
+	///
+	/// The clients are told first so that they stop sending. The workers stop
+	/// receiving and forward the packets in flight until they run dry or the
+	/// timeout expires. What is left is freed along with the client rings,
+	/// and the runtime is torn down last
+	fn shutdown(&self, mut stopping: Stopping<'_>) -> Fallible<()> {
+		let deadline = Instant::now() + self.shutdown_timeout;
+		info!("shutting down, draining for at most {:?}", self.shutdown_timeout);
+		let res = teardown(&mut stopping, deadline);
+		info!("engine stopped");
+		res
+	}
+
+	/// Free what the clients and the workers left behind, and the registry
+	/// Nobody receives or transmits anymore
+	fn free_clients(&self) {
+		let mut stranded = 0;
+		for port_id in self.port_ids() {
+			if let Some(backlog) = self.backlogs.get(&port_id) {
+				while let Some(pkts) = backlog.pop() {
+					stranded += pkts.len();
+				}
+			}
+		}
+		debug!(cond: stranded > 0, "freed {} packets that were never transmitted", stranded);
+
+		// frees the rings and what the clients left on them
+		for status in &[ClientStatus::STARTING, ClientStatus::READY, ClientStatus::DEAD] {
+			for id in self.clients_with(*status) {
+				self.remove_client(id);
+			}
+		}
+
+		// the memzone must be freed before the EAL is cleaned up
+		if let Ok(mut registry) = self.registry.write() {
+			registry.take();
+		}
+	}
+}
+
+/// The engine on its way down, and what it still has running
+struct Stopping<'a> {
+	engine: &'a Engine,
+	workers: Option<Workers>,
+	exporter: Option<MetricsHandle>,
+	runtime: Option<Runtime>,
+}
+
+impl Teardown for Stopping<'_> {
+	fn notify_clients(&mut self) {
+		match self.engine.control.notify_all(Response::ShuttingDown) {
+			Ok(notified) => info!("told {} clients the engine is stopping", notified),
+			Err(err) => warn!("failed to tell the clients the engine is stopping: {}", err),
+		}
+	}
+
+	fn drain_workers(&mut self, deadline: Instant) -> Fallible<()> {
+		match self.workers.take() {
+			Some(workers) => workers.stop(deadline),
+			None => Ok(()),
+		}
+	}
+
+	fn stop_exporter(&mut self) {
+		if let Some(exporter) = self.exporter.take() {
+			exporter.stop();
+		}
+	}
+
+	fn free_clients(&mut self) {
+		self.engine.free_clients();
+	}
+
+	fn stop_ports(&mut self) {
+		// stops the ports and cleans up the EAL
+		self.runtime.take();
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::net::UNTAGGED;
+	use std::net::Ipv4Addr;
+
+	fn container(last: u8, vlan: u16) -> ContainerInfo {
+		ContainerInfo {
+			mac: MacAddr::new(0x02, 0, 0, 0, 0, 0x01),
+			ip: Ipv4Addr::new(10, 0, 0, last),
+			vlan,
+		}
+	}
+
+	#[test]
+	fn ring_settings_on_top_of_defaults() {
+		let defaults = RingConfig::default();
+		let sockets = vec![SocketId::new(0), SocketId::new(1)];
+
+		let rings = ring_settings(&defaults, &RingRequest::default(), &sockets).unwrap();
+		assert_eq!(defaults.capacity, rings.capacity);
+		assert_eq!(defaults.flags(), rings.flags());
+		assert_eq!(None, rings.socket);
+
+		let asked = RingRequest {
+			capacity: Some(256),
+			single_consumer: Some(false),
+			socket: Some(SocketId::new(1)),
+			..RingRequest::default()
+		};
+		let rings = ring_settings(&defaults, &asked, &sockets).unwrap();
+		assert_eq!(256, rings.capacity);
+		assert!(rings.single_producer);
+		assert!(!rings.single_consumer);
+		assert_eq!(Some(SocketId::new(1)), rings.socket);
+	}
+
+	#[test]
+	fn ring_settings_validated() {
+		let defaults = RingConfig::default();
+		let sockets = vec![SocketId::new(0)];
+
+		let asked = RingRequest {
+			capacity: Some(1000),
+			..RingRequest::default()
+		};
+		assert_eq!(
+			Err(ProtocolError::InvalidRingCapacity(1000)),
+			ring_settings(&defaults, &asked, &sockets).map(|rings| rings.capacity)
+		);
+		let asked = RingRequest {
+			socket: Some(SocketId::new(1)),
+			..RingRequest::default()
+		};
+		assert_eq!(
+			Err(ProtocolError::UnknownSocket(SocketId::new(1))),
+			ring_settings(&defaults, &asked, &sockets).map(|rings| rings.capacity)
+		);
+	}
+
+	#[test]
+	fn flooding_stays_on_the_ingress_vlan() {
+		let clients = vec![(0, 10), (1, 20), (2, 10), (3, UNTAGGED)];
+		let ports = vec![0, 1];
+
+		assert_eq!(
+			vec![Endpoint::Client(2), Endpoint::Port(0), Endpoint::Port(1)],
+			flood_targets(Endpoint::Client(0), 10, &clients, &ports)
+		);
+		assert_eq!(
+			vec![Endpoint::Client(1), Endpoint::Port(1)],
+			flood_targets(Endpoint::Port(0), 20, &clients, &ports)
+		);
+		assert_eq!(
+			vec![Endpoint::Client(3), Endpoint::Port(0)],
+			flood_targets(Endpoint::Port(1), UNTAGGED, &clients, &ports)
+		);
+		// nobody else on the VLAN
+		assert_eq!(
+			Vec::<Endpoint>::new(),
+			flood_targets(Endpoint::Port(0), 30, &clients, &ports[..1])
+		);
+	}
+
+	#[test]
+	fn registration_refused_for_address_in_use() {
+		let fdb = Fdb::new();
+		assert_eq!(Ok(()), claim_address(&fdb, &container(1, 10), 0));
+		assert_eq!(
+			Err(ProtocolError::AddressInUse(MacAddr::new(0x02, 0, 0, 0, 0, 0x01), 10)),
+			claim_address(&fdb, &container(2, 10), 1)
+		);
+		assert_eq!(Some(Endpoint::Client(0)), fdb.lookup(&container(1, 10).mac, 10));
+		// the same MAC is free on another VLAN
+		assert_eq!(Ok(()), claim_address(&fdb, &container(2, 20), 1));
+
+		// and for anybody once its owner stopped
+		fdb.purge(Endpoint::Client(0));
+		assert_eq!(Ok(()), claim_address(&fdb, &container(2, 10), 1));
+		assert_eq!(Some(Endpoint::Client(1)), fdb.lookup(&container(2, 10).mac, 10));
+	}
+}
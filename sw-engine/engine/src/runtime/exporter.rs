@@ -0,0 +1,178 @@
+/*
+ * Created on Sun Oct 18 2026:20:41:17
+ * Created by Ratnadeep Bhattacharya
+ */
+
+use crate::{
+	debug, info,
+	metrics::{self, Measurement},
+	warn,
+};
+use failure::Fallible;
+use std::{
+	io::{self, Read, Write},
+	net::{SocketAddr, TcpListener, TcpStream},
+	sync::{
+		atomic::{AtomicBool, Ordering},
+		Arc,
+	},
+	thread,
+	time::Duration,
+};
+
+/// The path the metrics are served on
+const METRICS_PATH: &str = "/metrics";
+
+/// Scrapers that do not send their request within a second are hung up on
+const READ_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Requests are a single line and a few headers, anything longer is rejected
+const MAX_REQUEST: usize = 8192;
+
+/// Look for scrapes, and for a request to stop, every 100 ms
+const ACCEPT_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Serves the metrics over HTTP in the Prometheus text format
+///
+/// Scrapes are rare and small, they are answered one at a time on a thread
+/// of their own, away from the cores moving packets.
+pub struct MetricsServer {
+	listener: TcpListener,
+}
+
+impl MetricsServer {
+	/// Listen on `addr`
+	pub fn bind(addr: SocketAddr) -> Fallible<Self> {
+		let listener = TcpListener::bind(addr)?;
+		info!("serving metrics on http://{}{}", listener.local_addr()?, METRICS_PATH);
+		Ok(Self { listener })
+	}
+
+	/// The address the server listens on
+	pub fn local_addr(&self) -> Fallible<SocketAddr> {
+		Ok(self.listener.local_addr()?)
+	}
+
+	/// Answer scrapes with whatever `gather` measures at the time
+	/// until the returned handle is stopped
+	pub fn spawn<F>(self, gather: F) -> Fallible<MetricsHandle>
+	where
+		F: Fn() -> Vec<Measurement> + Send + 'static,
+	{
+		// polled, so that the thread notices when it is asked to stop
+		self.listener.set_nonblocking(true)?;
+		let stop = Arc::new(AtomicBool::new(false));
+		let stopped = stop.clone();
+		let thread = thread::Builder::new()
+			.name("metrics".into())
+			.spawn(move || {
+				while !stopped.load(Ordering::Relaxed) {
+					let res = match self.listener.accept() {
+						Ok((mut stream, _)) => respond(&mut stream, &gather),
+						Err(ref err) if err.kind() == io::ErrorKind::WouldBlock => {
+							thread::sleep(ACCEPT_INTERVAL);
+							Ok(())
+						}
+						Err(err) => Err(err),
+					};
+					if let Err(err) = res {
+						debug!("failed to answer scrape: {}", err);
+					}
+				}
+				debug!("stopped serving metrics");
+			})?;
+		Ok(MetricsHandle { stop, thread })
+	}
+}
+
+/// A running metrics server
+///
+/// It reads the engine state on every scrape, so it must be stopped before
+/// that state is freed. Dropping the handle leaves it running
+pub struct MetricsHandle {
+	stop: Arc<AtomicBool>,
+	thread: thread::JoinHandle<()>,
+}
+
+impl MetricsHandle {
+	/// Stop answering scrapes and wait for the scrape in progress
+	pub fn stop(self) {
+		self.stop.store(true, Ordering::Relaxed);
+		if self.thread.join().is_err() {
+			warn!("metrics server panicked");
+		}
+	}
+}
+
+/// Read the request head and answer it
+fn respond<F>(stream: &mut TcpStream, gather: &F) -> io::Result<()>
+where
+	F: Fn() -> Vec<Measurement>,
+{
+	// the listener is non-blocking, the streams it accepts must not be
+	stream.set_nonblocking(false)?;
+	stream.set_read_timeout(Some(READ_TIMEOUT))?;
+	let mut head = Vec::new();
+	let mut buf = [0; 1024];
+	while !head.windows(4).any(|w| w == b"\r\n\r\n") {
+		if head.len() > MAX_REQUEST {
+			return reply(stream, "413 Payload Too Large", "");
+		}
+		match stream.read(&mut buf)? {
+			0 => break,
+			n => head.extend_from_slice(&buf[..n]),
+		}
+	}
+
+	let head = String::from_utf8_lossy(&head);
+	let mut request = head.lines().next().unwrap_or_default().split_whitespace();
+	match (request.next(), request.next()) {
+		(Some("GET"), Some(METRICS_PATH)) => reply(stream, "200 OK", &metrics::encode(&gather())),
+		(Some("GET"), _) => reply(stream, "404 Not Found", ""),
+		_ => reply(stream, "405 Method Not Allowed", ""),
+	}
+}
+
+fn reply(stream: &mut TcpStream, status: &str, body: &str) -> io::Result<()> {
+	write!(
+		stream,
+		"HTTP/1.1 {}\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+		status,
+		body.len(),
+		body
+	)?;
+	stream.flush()
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn get(addr: SocketAddr, path: &str) -> String {
+		let mut stream = TcpStream::connect(addr).unwrap();
+		write!(stream, "GET {} HTTP/1.1\r\nHost: localhost\r\n\r\n", path).unwrap();
+		let mut response = String::new();
+		stream.read_to_string(&mut response).unwrap();
+		response
+	}
+
+	#[test]
+	fn serve_scrapes() {
+		let server = MetricsServer::bind("127.0.0.1:0".parse().unwrap()).unwrap();
+		let addr = server.local_addr().unwrap();
+		let handle = server
+			.spawn(|| vec![Measurement::gauge("fdb.entries", vec![], 4)])
+			.unwrap();
+
+		let response = get(addr, METRICS_PATH);
+		assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+		assert!(response.ends_with("\r\n\r\n# TYPE fdb_entries gauge\nfdb_entries 4\n"));
+
+		let response = get(addr, "/");
+		assert!(response.starts_with("HTTP/1.1 404 Not Found\r\n"));
+
+		// the listener goes with the thread
+		handle.stop();
+		assert!(TcpStream::connect(addr).is_err());
+	}
+}
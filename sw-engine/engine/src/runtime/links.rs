@@ -0,0 +1,138 @@
+/*
+ * Created on Sun Oct 18 2026:19:12:44
+ * Created by Ratnadeep Bhattacharya
+ */
+
+use crate::{dpdk::LinkStatus, metrics::Measurement};
+use chashmap::CHashMap;
+
+/// The last known link status of each port
+///
+/// A port whose link was never seen is taken to be up, so that packets
+/// are not held back before the first report
+#[derive(Default)]
+pub struct PortLinks {
+	names: CHashMap<u16, String>,
+	links: CHashMap<u16, LinkStatus>,
+}
+
+impl PortLinks {
+	/// Returns an empty set of links
+	pub fn new() -> Self {
+		Self::default()
+	}
+
+	/// Name port `port_id` in the metrics
+	pub fn set_name(&self, port_id: u16, name: &str) {
+		self.names.insert(port_id, name.to_owned());
+	}
+
+	/// Record the link status of port `port_id`
+	/// Returns whether it changed
+	pub fn update(&self, port_id: u16, status: LinkStatus) -> bool {
+		let changed = match self.links.get(&port_id) {
+			Some(link) => *link != status,
+			None => true,
+		};
+		if changed {
+			self.links.insert(port_id, status);
+		}
+		changed
+	}
+
+	/// Whether the link of port `port_id` is up
+	pub fn is_up(&self, port_id: u16) -> bool {
+		self.links.get(&port_id).map_or(true, |link| link.up)
+	}
+
+	/// The ports of `port_ids` whose link is up, the others get no flooding
+	pub fn up(&self, port_ids: impl IntoIterator<Item = u16>) -> Vec<u16> {
+		port_ids.into_iter().filter(|id| self.is_up(*id)).collect()
+	}
+
+	/// The link metrics of the ports of `port_ids` seen so far
+	/// Labeled with the name of the port, or its id until it is named
+	pub fn measurements(&self, port_ids: impl IntoIterator<Item = u16>) -> Vec<Measurement> {
+		let mut measurements = vec![];
+		for port_id in port_ids {
+			if let Some(link) = self.links.get(&port_id) {
+				let name = match self.names.get(&port_id) {
+					Some(name) => name.clone(),
+					None => port_id.to_string(),
+				};
+				let labels = vec![("port", name)];
+				measurements.push(Measurement::gauge("port.link_up", labels.clone(), link.up as u64));
+				measurements.push(Measurement::gauge("port.link_speed", labels, link.speed as u64));
+			}
+		}
+		measurements
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn up(speed: u32) -> LinkStatus {
+		LinkStatus {
+			up: true,
+			speed,
+			full_duplex: true,
+			autoneg: true,
+		}
+	}
+
+	#[test]
+	fn update_reports_changes() {
+		let links = PortLinks::new();
+		assert!(links.update(0, up(10_000)));
+		assert!(!links.update(0, up(10_000)));
+		assert!(links.update(0, up(1_000)));
+		assert!(links.update(0, LinkStatus::default()));
+		assert!(!links.update(0, LinkStatus::default()));
+		assert!(links.update(1, LinkStatus::default()));
+	}
+
+	#[test]
+	fn unseen_link_is_up() {
+		let links = PortLinks::new();
+		assert!(links.is_up(0));
+		links.update(0, LinkStatus::default());
+		assert!(!links.is_up(0));
+		links.update(0, up(10_000));
+		assert!(links.is_up(0));
+	}
+
+	#[test]
+	fn no_flooding_to_ports_down() {
+		let links = PortLinks::new();
+		links.update(0, up(10_000));
+		links.update(1, LinkStatus::default());
+		assert_eq!(vec![0, 2], links.up(vec![0, 1, 2]));
+
+		links.update(1, up(10_000));
+		links.update(2, LinkStatus::default());
+		assert_eq!(vec![0, 1], links.up(vec![0, 1, 2]));
+	}
+
+	#[test]
+	fn measurements_labeled_with_port_name() {
+		let links = PortLinks::new();
+		links.set_name(0, "eth0");
+		links.update(0, up(10_000));
+		links.update(1, LinkStatus::default());
+
+		let measurements = links.measurements(vec![0, 1, 2]);
+		assert_eq!(4, measurements.len());
+		let value = |name: &str, port: &str| {
+			measurements
+				.iter()
+				.find(|m| m.name == name && m.labels == vec![("port", port.to_owned())])
+				.map(|m| m.value)
+		};
+		assert_eq!(Some(1), value("port.link_up", "eth0"));
+		assert_eq!(Some(10_000), value("port.link_speed", "eth0"));
+		assert_eq!(Some(0), value("port.link_up", "1"));
+		assert_eq!(Some(0), value("port.link_speed", "1"));
+	}
+}
@@ -3,6 +3,22 @@
  * Created by Ratnadeep Bhattacharya
  */
 
+mod client_ids;
+mod control;
 mod engine;
+mod exporter;
+mod links;
+mod registry;
+mod setup;
+mod shutdown;
+mod worker;
 
+pub use client_ids::*;
+pub use control::*;
 pub use engine::*;
+pub use exporter::*;
+pub use links::*;
+pub use registry::*;
+pub use setup::*;
+pub use shutdown::*;
+pub use worker::*;
@@ -0,0 +1,456 @@
+/*
+ * Created on Sun Oct 18 2026:19:12:40
+ * Created by Ratnadeep Bhattacharya
+ */
+
+//! The client registry the engine publishes in shared memory
+//!
+//! The engine keeps a fixed-layout table of its clients in a named memzone.
+//! Sidecars and diagnostic tools attached to the engine as secondary
+//! processes read it straight from hugepage memory, without going through
+//! the control socket.
+//!
+//! The zone starts with a header holding a magic number, the layout version
+//! and the number of slots, followed by one slot per client id. Each slot is
+//! guarded by a sequence number that is odd while the engine rewrites the
+//! slot, so readers never see a half written entry. The packet counters are
+//! atomics and are updated outside of the sequence, the engine copies them
+//! from the counters of the client's channel.
+
+use super::ClientStatus;
+use crate::{
+	dockerlib::ContainerInfo,
+	dpdk::{Memzone, SocketId},
+	metrics::Totals,
+	net::MacAddr,
+};
+use failure::{Fail, Fallible};
+use std::{
+	hint, mem,
+	net::Ipv4Addr,
+	ptr,
+	sync::{
+		atomic::{fence, AtomicU32, AtomicU64, Ordering},
+		Mutex,
+	},
+};
+
+/// Name of the memzone the registry lives in
+pub const REGISTRY_NAME: &str = "sw_engine_registry";
+
+/// Version of the registry layout, bumped whenever `Header` or `Slot` change
+pub const REGISTRY_VERSION: u32 = 1;
+
+/// Number of client ids published, clients with higher ids are left out
+pub const REGISTRY_SLOTS: usize = 256;
+
+/// Marks the start of a registry, "SWRG"
+const REGISTRY_MAGIC: u32 = 0x5357_5247;
+
+/// Room for a ring name and its NUL, as in RTE_MEMZONE_NAMESIZE
+const RING_NAME_LEN: usize = 32;
+
+/// Status of a slot no client holds
+const SLOT_FREE: u32 = 0;
+
+/// Error indicating the registry could not be attached to
+#[derive(Debug, Fail)]
+pub enum RegistryError {
+	/// The memzone does not start with a registry header
+	#[fail(display = "Memzone {} does not hold a client registry.", _0)]
+	NotARegistry(String),
+
+	/// The engine publishes another version of the layout
+	#[fail(display = "Unsupported registry version {}, expected {}.", got, expected)]
+	UnsupportedVersion { expected: u32, got: u32 },
+}
+
+#[repr(C)]
+struct Header {
+	magic: u32,
+	version: u32,
+	slots: u32,
+	slot_size: u32,
+}
+
+#[repr(C)]
+struct Slot {
+	seq: AtomicU32,
+	status: u32,
+	id: u16,
+	vlan: u16,
+	ip: [u8; 4],
+	mac: [u8; 6],
+	_pad: [u8; 2],
+	rx_ring: [u8; RING_NAME_LEN],
+	tx_ring: [u8; RING_NAME_LEN],
+	rx_pkts: AtomicU64,
+	tx_pkts: AtomicU64,
+	drops: AtomicU64,
+}
+
+/// A client as published in the registry
+#[derive(Clone, Debug, PartialEq)]
+pub struct ClientEntry {
+	pub id: u16,
+	pub status: ClientStatus,
+	pub mac: MacAddr,
+	pub ip: Ipv4Addr,
+	pub vlan: u16,
+	pub rx_ring: String,
+	pub tx_ring: String,
+	/// Packets the engine delivered to the client
+	/// The counters are refreshed every second
+	pub rx_pkts: u64,
+	/// Packets the client sent to the engine
+	pub tx_pkts: u64,
+	/// Packets for the client the engine had to drop
+	pub drops: u64,
+}
+
+fn status_code(status: ClientStatus) -> u32 {
+	match status {
+		ClientStatus::STARTING => 1,
+		ClientStatus::READY => 2,
+		ClientStatus::DEAD => 3,
+	}
+}
+
+fn status_from_code(code: u32) -> Option<ClientStatus> {
+	match code {
+		1 => Some(ClientStatus::STARTING),
+		2 => Some(ClientStatus::READY),
+		3 => Some(ClientStatus::DEAD),
+		_ => None,
+	}
+}
+
+fn name_to_bytes(name: &str) -> [u8; RING_NAME_LEN] {
+	let mut bytes = [0; RING_NAME_LEN];
+	// always leave the NUL
+	let len = name.len().min(RING_NAME_LEN - 1);
+	bytes[..len].copy_from_slice(&name.as_bytes()[..len]);
+	bytes
+}
+
+fn name_from_bytes(bytes: &[u8; RING_NAME_LEN]) -> String {
+	let len = bytes.iter().position(|b| *b == 0).unwrap_or(RING_NAME_LEN);
+	String::from_utf8_lossy(&bytes[..len]).into_owned()
+}
+
+/// The registry laid over a block of memory
+struct Table {
+	header: *mut Header,
+	slots: *mut Slot,
+}
+
+impl Table {
+	/// Bytes needed to hold the registry
+	fn size() -> usize {
+		mem::size_of::<Header>() + REGISTRY_SLOTS * mem::size_of::<Slot>()
+	}
+
+	/// Lay the table over `base`, which must hold at least `size()` bytes
+	unsafe fn new(base: *mut u8) -> Self {
+		Self {
+			header: base as *mut Header,
+			slots: base.add(mem::size_of::<Header>()) as *mut Slot,
+		}
+	}
+
+	/// Clear the memory and write the header
+	fn init(&self) {
+		unsafe {
+			ptr::write_bytes(self.header as *mut u8, 0, Table::size());
+			ptr::write_volatile(
+				self.header,
+				Header {
+					magic: REGISTRY_MAGIC,
+					version: REGISTRY_VERSION,
+					slots: REGISTRY_SLOTS as u32,
+					slot_size: mem::size_of::<Slot>() as u32,
+				},
+			);
+		}
+	}
+
+	/// Check that the memory holds a registry of this layout
+	fn check(&self, name: &str) -> Fallible<()> {
+		let header = unsafe { ptr::read_volatile(self.header) };
+		if header.magic != REGISTRY_MAGIC {
+			return Err(RegistryError::NotARegistry(name.to_owned()).into());
+		}
+		// the slot count and size are part of the version
+		if header.version != REGISTRY_VERSION
+			|| header.slots != REGISTRY_SLOTS as u32
+			|| header.slot_size != mem::size_of::<Slot>() as u32
+		{
+			return Err(RegistryError::UnsupportedVersion {
+				expected: REGISTRY_VERSION,
+				got: header.version,
+			}
+			.into());
+		}
+		Ok(())
+	}
+
+	fn slot(&self, id: u16) -> Option<*mut Slot> {
+		if (id as usize) < REGISTRY_SLOTS {
+			Some(unsafe { self.slots.add(id as usize) })
+		} else {
+			None
+		}
+	}
+
+	fn counters(&self, id: u16) -> Option<&Slot> {
+		self.slot(id).map(|slot| unsafe { &*slot })
+	}
+
+	fn set_counters(&self, id: u16, rx_pkts: u64, tx_pkts: u64, drops: u64) {
+		if let Some(slot) = self.counters(id) {
+			slot.rx_pkts.store(rx_pkts, Ordering::Relaxed);
+			slot.tx_pkts.store(tx_pkts, Ordering::Relaxed);
+			slot.drops.store(drops, Ordering::Relaxed);
+		}
+	}
+
+	/// Rewrite slot `id` with `f`, readers retry while it is in progress
+	/// Writers must be serialized by the caller
+	fn write<F: FnOnce(*mut Slot)>(&self, id: u16, f: F) -> bool {
+		let slot = match self.slot(id) {
+			Some(slot) => slot,
+			None => return false,
+		};
+		unsafe {
+			let seq = (*slot).seq.load(Ordering::Relaxed);
+			(*slot).seq.store(seq.wrapping_add(1), Ordering::Relaxed);
+			fence(Ordering::Release);
+			f(slot);
+			(*slot).seq.store(seq.wrapping_add(2), Ordering::Release);
+		}
+		true
+	}
+
+	/// Read a consistent copy of slot `id`, None if no client holds it
+	fn read(&self, id: u16) -> Option<ClientEntry> {
+		let slot = self.slot(id)?;
+		loop {
+			unsafe {
+				let seq = (*slot).seq.load(Ordering::Acquire);
+				if seq & 1 == 1 {
+					hint::spin_loop();
+					continue;
+				}
+				let status = ptr::read_volatile(&(*slot).status);
+				let vlan = ptr::read_volatile(&(*slot).vlan);
+				let ip = ptr::read_volatile(&(*slot).ip);
+				let mac = ptr::read_volatile(&(*slot).mac);
+				let rx_ring = ptr::read_volatile(&(*slot).rx_ring);
+				let tx_ring = ptr::read_volatile(&(*slot).tx_ring);
+				fence(Ordering::Acquire);
+				if (*slot).seq.load(Ordering::Relaxed) != seq {
+					continue;
+				}
+				let status = status_from_code(status)?;
+				return Some(ClientEntry {
+					id,
+					status,
+					mac: MacAddr::new(mac[0], mac[1], mac[2], mac[3], mac[4], mac[5]),
+					ip: Ipv4Addr::from(ip),
+					vlan,
+					rx_ring: name_from_bytes(&rx_ring),
+					tx_ring: name_from_bytes(&tx_ring),
+					rx_pkts: (*slot).rx_pkts.load(Ordering::Relaxed),
+					tx_pkts: (*slot).tx_pkts.load(Ordering::Relaxed),
+					drops: (*slot).drops.load(Ordering::Relaxed),
+				});
+			}
+		}
+	}
+
+	fn publish(&self, id: u16, info: &ContainerInfo, rx_ring: &str, tx_ring: &str) -> bool {
+		self.write(id, |slot| unsafe {
+			(*slot).status = status_code(ClientStatus::STARTING);
+			(*slot).id = id;
+			(*slot).vlan = info.vlan;
+			(*slot).ip = info.ip.octets();
+			(*slot).mac = info.mac.octets();
+			(*slot).rx_ring = name_to_bytes(rx_ring);
+			(*slot).tx_ring = name_to_bytes(tx_ring);
+			// a recycled id starts counting afresh
+			(*slot).rx_pkts.store(0, Ordering::Relaxed);
+			(*slot).tx_pkts.store(0, Ordering::Relaxed);
+			(*slot).drops.store(0, Ordering::Relaxed);
+		})
+	}
+
+	fn set_status(&self, id: u16, status: ClientStatus) -> bool {
+		self.write(id, |slot| unsafe { (*slot).status = status_code(status) })
+	}
+
+	fn clear(&self, id: u16) -> bool {
+		self.write(id, |slot| unsafe { (*slot).status = SLOT_FREE })
+	}
+}
+
+/// The client registry in shared memory
+///
+/// The engine creates it and is the only writer, every other process
+/// attaches to it read only.
+pub struct ClientRegistry {
+	table: Table,
+	// serializes the writers, the counters are atomics and do not take it
+	writer: Mutex<()>,
+	// keeps the memory mapped, freed with the registry if this process created it
+	_zone: Memzone,
+}
+
+// the table points into the memzone, which lives as long as the registry,
+// and concurrent access to the slots goes through the sequence numbers
+unsafe impl Send for ClientRegistry {}
+unsafe impl Sync for ClientRegistry {}
+
+impl ClientRegistry {
+	/// Reserve and initialize the registry, done by the engine
+	pub fn create(socket_id: SocketId) -> Fallible<Self> {
+		let zone = Memzone::new(REGISTRY_NAME.to_owned(), Table::size(), socket_id)?;
+		let table = unsafe { Table::new(zone.addr() as *mut u8) };
+		table.init();
+		Ok(Self {
+			table,
+			writer: Mutex::new(()),
+			_zone: zone,
+		})
+	}
+
+	/// Attach to the registry of a running engine
+	///
+	/// # Errors
+	///
+	/// If the engine has not published a registry, `MemzoneError::NotFound`
+	/// is returned. If it publishes another layout,
+	/// `RegistryError::UnsupportedVersion` is returned.
+	pub fn attach() -> Fallible<Self> {
+		let zone = Memzone::lookup(REGISTRY_NAME)?;
+		if zone.len() < Table::size() {
+			return Err(RegistryError::NotARegistry(REGISTRY_NAME.to_owned()).into());
+		}
+		let table = unsafe { Table::new(zone.addr() as *mut u8) };
+		table.check(REGISTRY_NAME)?;
+		Ok(Self {
+			table,
+			writer: Mutex::new(()),
+			_zone: zone,
+		})
+	}
+
+	/// Client `id`, None if it is not registered or not published
+	pub fn entry(&self, id: u16) -> Option<ClientEntry> {
+		self.table.read(id)
+	}
+
+	/// Every published client, by id
+	pub fn entries(&self) -> Vec<ClientEntry> {
+		(0..REGISTRY_SLOTS as u16)
+			.filter_map(|id| self.table.read(id))
+			.collect()
+	}
+
+	/// Publish newly registered client `id` as STARTING
+	/// Returns false if the id is beyond the registry
+	pub(crate) fn publish(&self, id: u16, info: &ContainerInfo, rx_ring: &str, tx_ring: &str) -> bool {
+		let _guard = self.writer.lock();
+		self.table.publish(id, info, rx_ring, tx_ring)
+	}
+
+	/// Update the status of client `id`
+	pub(crate) fn set_status(&self, id: u16, status: ClientStatus) {
+		let _guard = self.writer.lock();
+		self.table.set_status(id, status);
+	}
+
+	/// Remove client `id` from the registry
+	pub(crate) fn clear(&self, id: u16) {
+		let _guard = self.writer.lock();
+		self.table.clear(id);
+	}
+
+	/// Publish the counters of the channel of client `id`
+	/// `rx` counts the packets towards the client, `tx` those from it
+	pub(crate) fn publish_counters(&self, id: u16, rx: &Totals, tx: &Totals) {
+		let drops = rx.dropped.iter().sum();
+		self.table.set_counters(id, rx.packets, tx.packets, drops);
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	// memory for a table, aligned for the counters
+	fn memory() -> Vec<u64> {
+		vec![0; Table::size() / mem::size_of::<u64>() + 1]
+	}
+
+	#[test]
+	fn publish_and_read() {
+		let mut mem = memory();
+		let table = unsafe { Table::new(mem.as_mut_ptr() as *mut u8) };
+		table.init();
+		table.check(REGISTRY_NAME).unwrap();
+
+		let info = ContainerInfo {
+			mac: MacAddr::new(0x02, 0, 0, 0, 0, 0x03),
+			ip: Ipv4Addr::new(10, 0, 0, 3),
+			vlan: 7,
+		};
+		assert!(table.publish(3, &info, "RX-3", "TX-3"));
+		assert!(!table.publish(REGISTRY_SLOTS as u16, &info, "RX", "TX"));
+		table.set_status(3, ClientStatus::READY);
+		table.set_counters(3, 5, 2, 1);
+
+		assert_eq!(
+			Some(ClientEntry {
+				id: 3,
+				status: ClientStatus::READY,
+				mac: info.mac,
+				ip: info.ip,
+				vlan: 7,
+				rx_ring: "RX-3".into(),
+				tx_ring: "TX-3".into(),
+				rx_pkts: 5,
+				tx_pkts: 2,
+				drops: 1,
+			}),
+			table.read(3)
+		);
+		assert_eq!(None, table.read(2));
+
+		// a recycled id starts counting afresh
+		assert!(table.publish(3, &info, "RX-3", "TX-3"));
+		let entry = table.read(3).unwrap();
+		assert_eq!((0, 0, 0), (entry.rx_pkts, entry.tx_pkts, entry.drops));
+
+		table.clear(3);
+		assert_eq!(None, table.read(3));
+	}
+
+	#[test]
+	fn reject_other_layout() {
+		let mut mem = memory();
+		let table = unsafe { Table::new(mem.as_mut_ptr() as *mut u8) };
+		assert!(table.check(REGISTRY_NAME).is_err());
+
+		table.init();
+		unsafe { (*table.header).version = REGISTRY_VERSION + 1 };
+		match table.check(REGISTRY_NAME) {
+			Err(err) => match err.downcast::<RegistryError>() {
+				Ok(RegistryError::UnsupportedVersion { got, .. }) => {
+					assert_eq!(REGISTRY_VERSION + 1, got)
+				}
+				other => panic!("unexpected {:?}", other),
+			},
+			Ok(_) => panic!("accepted another version"),
+		}
+	}
+}
@@ -0,0 +1,151 @@
+/*
+ * Created on Sun Oct 18 2026:10:42:17
+ * Created by Ratnadeep Bhattacharya
+ */
+
+use crate::{
+	config::RuntimeConfig,
+	debug,
+	dpdk::{self, mempool_name, Mempool, Port, PortBuilder, PortError, SocketId},
+	ensure, info, warn, PORTMAP, PORTS,
+};
+use dashmap::DashMap;
+use failure::{Fail, Fallible};
+use std::collections::BTreeSet;
+
+/// Runtime errors
+#[derive(Debug, Fail)]
+pub enum RuntimeError {
+	/// The runtime can only be built once per process
+	#[fail(display = "The runtime is already built.")]
+	AlreadyBuilt,
+	/// The engine owns the ports, it can not run as a secondary process
+	#[fail(display = "The runtime can not be built by a secondary process.")]
+	Secondary,
+}
+
+/// The DPDK resources the engine runs on
+///
+/// Building the runtime initializes the EAL, creates one mempool per socket
+/// in use, and configures and starts every port. The port queues are
+/// published in `PORTS`, and `PORTMAP` is set up for the workers.
+/// Dropping the runtime stops the ports, frees the mempools and cleans up
+/// the EAL. `PORTS` can not be unset, its queues must not be used after.
+pub struct Runtime {
+	ports: Vec<Port>,
+	mempools: Vec<Mempool>,
+	config: RuntimeConfig,
+}
+
+impl Runtime {
+	/// Builds the runtime from `config`
+	///
+	/// # Errors
+	///
+	/// If the configuration is not valid, `ConfigError` is returned.
+	/// If the runtime was built before or the process is a secondary
+	/// one, `RuntimeError` is returned. Any failure to set up DPDK tears
+	/// down what was already set up
+	pub fn build(config: RuntimeConfig) -> Fallible<Self> {
+		config.validate()?;
+		ensure!(!config.secondary, RuntimeError::Secondary);
+		ensure!(PORTS.try_get().is_none(), RuntimeError::AlreadyBuilt);
+		info!("building runtime {:?}", config);
+
+		dpdk::eal_init(config.to_eal_args())?;
+		// from here on dropping the runtime undoes whatever was set up
+		let mut runtime = Runtime {
+			ports: vec![],
+			mempools: vec![],
+			config,
+		};
+
+		// the main thread runs the control tasks on the master core
+		runtime.config.master_core.set_thread_affinity()?;
+
+		// one mempool per socket the cores are on
+		let sockets = runtime
+			.config
+			.all_cores()
+			.iter()
+			.map(|core| core.socket_id())
+			.collect::<BTreeSet<SocketId>>();
+		for socket in sockets {
+			let mempool = Mempool::new(
+				mempool_name(socket),
+				runtime.config.mempool.capacity,
+				runtime.config.mempool.cache_size,
+				socket,
+			)?;
+			debug!("{:?}", mempool);
+			runtime.mempools.push(mempool);
+		}
+
+		// mbufs allocated on the main thread come from its own socket
+		dpdk::attach_mempool(runtime.config.master_core.socket_id())?;
+
+		for conf in runtime.config.ports.clone() {
+			let mut port = PortBuilder::new(conf.name.clone(), conf.device.clone())?
+				.cores(&conf.cores)?
+				.mempools(&mut runtime.mempools)
+				.rx_tx_queue_capacity(conf.rxd, conf.txd)?
+				.finish(conf.multicast)?;
+			port.start()?;
+			runtime.ports.push(port);
+		}
+
+		for port in runtime.ports.iter_mut() {
+			// devices without link state change interrupts are polled instead
+			if let Err(err) = port.watch_link() {
+				match err.downcast_ref::<PortError>() {
+					Some(PortError::LscUnsupported(_)) => debug!("{}", err),
+					_ => return Err(err),
+				}
+			}
+			info!("port {} link is {}", port.name(), port.link_status());
+		}
+
+		let mut queues = runtime
+			.ports
+			.iter()
+			.flat_map(|port| port.queues().values().cloned())
+			.collect::<Vec<_>>();
+		queues.sort_by_key(|queue| queue.get_portid());
+		PORTS.set(queues);
+		PORTMAP.set(DashMap::default());
+
+		info!("runtime built");
+		Ok(runtime)
+	}
+
+	/// Returns the settings the runtime was built from
+	pub fn config(&self) -> &RuntimeConfig {
+		&self.config
+	}
+
+	/// Returns the ports of the runtime
+	pub fn ports(&self) -> &[Port] {
+		&self.ports
+	}
+
+	/// Returns the port named `name`
+	pub fn port(&self, name: &str) -> Option<&Port> {
+		self.ports.iter().find(|port| port.name() == name)
+	}
+}
+
+impl Drop for Runtime {
+	fn drop(&mut self) {
+		debug!("tearing down runtime");
+		for port in self.ports.iter_mut() {
+			port.stop();
+		}
+		// the ports and mempools must be freed before the EAL goes
+		self.ports.clear();
+		self.mempools.clear();
+		if let Err(err) = dpdk::eal_cleanup() {
+			warn!("EAL cleanup failed: {}", err);
+		}
+		info!("runtime torn down");
+	}
+}
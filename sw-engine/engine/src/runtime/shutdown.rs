@@ -0,0 +1,187 @@
+/*
+ * Created on Sun Oct 18 2026:16:21:09
+ * Created by Ratnadeep Bhattacharya
+ */
+
+use crate::info;
+use failure::{Fail, Fallible};
+use std::{
+	io, mem, ptr,
+	sync::atomic::{AtomicBool, Ordering},
+	time::Instant,
+};
+
+/// Set once the engine is asked to stop
+static SHUTDOWN: ShutdownFlag = ShutdownFlag::new();
+
+/// A request to stop, raised from a signal handler or by hand
+///
+/// Raising it is async-signal-safe. It stays raised until reset
+#[derive(Debug, Default)]
+pub struct ShutdownFlag(AtomicBool);
+
+impl ShutdownFlag {
+	/// A flag that is not raised
+	pub const fn new() -> Self {
+		ShutdownFlag(AtomicBool::new(false))
+	}
+
+	/// Raise the flag
+	pub fn request(&self) {
+		self.0.store(true, Ordering::SeqCst);
+	}
+
+	/// Whether the flag is raised
+	pub fn is_requested(&self) -> bool {
+		self.0.load(Ordering::SeqCst)
+	}
+
+	/// Lower the flag again
+	pub fn reset(&self) {
+		self.0.store(false, Ordering::SeqCst);
+	}
+}
+
+/// Signal errors
+#[derive(Debug, Fail)]
+pub enum SignalError {
+	/// The handler of a signal could not be installed
+	#[fail(display = "Failed to handle signal {}: {}", _0, _1)]
+	Install(libc::c_int, io::Error),
+}
+
+// only async-signal-safe work in here, the engine polls the flag
+extern "C" fn on_signal(_: libc::c_int) {
+	SHUTDOWN.request();
+}
+
+/// Ask the engine to stop on SIGINT and SIGTERM
+///
+/// The first signal starts a graceful shutdown. The handlers reset
+/// themselves, so a second signal kills the process right away
+pub fn handle_signals() -> Fallible<()> {
+	for &signal in &[libc::SIGINT, libc::SIGTERM] {
+		unsafe {
+			let mut action: libc::sigaction = mem::zeroed();
+			action.sa_sigaction = on_signal as extern "C" fn(libc::c_int) as libc::sighandler_t;
+			action.sa_flags = libc::SA_RESETHAND;
+			libc::sigemptyset(&mut action.sa_mask);
+			if libc::sigaction(signal, &action, ptr::null_mut()) != 0 {
+				return Err(SignalError::Install(signal, io::Error::last_os_error()).into());
+			}
+		}
+	}
+	info!("stopping on SIGINT or SIGTERM");
+	Ok(())
+}
+
+/// Ask the engine to stop, as if it got a signal
+pub fn request_shutdown() {
+	SHUTDOWN.request();
+}
+
+/// Whether the engine was asked to stop
+pub fn shutdown_requested() -> bool {
+	SHUTDOWN.is_requested()
+}
+
+/// The steps of stopping the engine, run in order by `teardown`
+pub(crate) trait Teardown {
+	/// Tell the clients the engine is stopping, so that they stop sending
+	fn notify_clients(&mut self);
+
+	/// Stop the workers, which forward the packets in flight until `deadline`
+	fn drain_workers(&mut self, deadline: Instant) -> Fallible<()>;
+
+	/// Stop serving the metrics, which read the state freed next
+	fn stop_exporter(&mut self);
+
+	/// Free the client rings, the packets left over and the client registry
+	fn free_clients(&mut self);
+
+	/// Stop the ports and clean up the EAL
+	fn stop_ports(&mut self);
+}
+
+/// Stop the engine, every step runs even if draining the workers failed
+pub(crate) fn teardown(steps: &mut impl Teardown, deadline: Instant) -> Fallible<()> {
+	steps.notify_clients();
+	let res = steps.drain_workers(deadline);
+	steps.stop_exporter();
+	steps.free_clients();
+	steps.stop_ports();
+	res
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn flag_starts_lowered() {
+		assert!(!ShutdownFlag::new().is_requested());
+		assert!(!ShutdownFlag::default().is_requested());
+	}
+
+	#[derive(Default)]
+	struct Steps {
+		done: Vec<&'static str>,
+		fail_drain: bool,
+	}
+
+	impl Teardown for Steps {
+		fn notify_clients(&mut self) {
+			self.done.push("notify_clients");
+		}
+
+		fn drain_workers(&mut self, _: Instant) -> Fallible<()> {
+			self.done.push("drain_workers");
+			if self.fail_drain {
+				return Err(failure::format_err!("worker panicked"));
+			}
+			Ok(())
+		}
+
+		fn stop_exporter(&mut self) {
+			self.done.push("stop_exporter");
+		}
+
+		fn free_clients(&mut self) {
+			self.done.push("free_clients");
+		}
+
+		fn stop_ports(&mut self) {
+			self.done.push("stop_ports");
+		}
+	}
+
+	const ORDER: &[&str] = &["notify_clients", "drain_workers", "stop_exporter", "free_clients", "stop_ports"];
+
+	#[test]
+	fn teardown_order() {
+		let mut steps = Steps::default();
+		assert!(teardown(&mut steps, Instant::now()).is_ok());
+		assert_eq!(ORDER, steps.done.as_slice());
+	}
+
+	#[test]
+	fn teardown_completes_when_draining_fails() {
+		let mut steps = Steps {
+			fail_drain: true,
+			..Steps::default()
+		};
+		assert!(teardown(&mut steps, Instant::now()).is_err());
+		assert_eq!(ORDER, steps.done.as_slice());
+	}
+
+	#[test]
+	fn flag_stays_raised_until_reset() {
+		let flag = ShutdownFlag::new();
+		flag.request();
+		assert!(flag.is_requested());
+		flag.request();
+		assert!(flag.is_requested());
+		flag.reset();
+		assert!(!flag.is_requested());
+	}
+}
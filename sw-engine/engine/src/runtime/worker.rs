@@ -0,0 +1,260 @@
+/*
+ * Created on Sun Oct 18 2026:13:05:48
+ * Created by Ratnadeep Bhattacharya
+ */
+
+use crate::{
+	debug,
+	dpdk::{self, CoreId, PortQueue},
+	info,
+	runtime::Runtime,
+	warn,
+};
+use failure::{Fail, Fallible};
+use futures::channel::oneshot;
+use std::{cell::RefCell, sync::mpsc, thread::JoinHandle, time::Instant};
+
+thread_local! {
+	/// Port queues owned by the worker running on the current thread
+	///
+	/// It's set when the worker starts, and stays empty on the master core
+	/// Only the owner may receive from or transmit through these queues
+	static QUEUES: RefCell<Vec<PortQueue>> = RefCell::new(Vec::new());
+}
+
+/// Run `f` over the port queues of the current worker
+pub(crate) fn with_local_queues<R>(f: impl FnOnce(&[PortQueue]) -> R) -> R {
+	QUEUES.with(|queues| f(&queues.borrow()))
+}
+
+/// Worker errors
+#[derive(Debug, Fail)]
+pub enum WorkerError {
+	/// The worker thread panicked
+	#[fail(display = "Worker on {:?} panicked.", _0)]
+	Panicked(CoreId),
+	/// The worker could not settle on its core
+	#[fail(display = "Worker on {:?} failed to start: {}", _0, _1)]
+	NotStarted(CoreId, String),
+}
+
+/// Where a worker runs and the port queues it owns
+pub(crate) struct Placement {
+	pub(crate) index: usize,
+	pub(crate) core: CoreId,
+	queues: Vec<PortQueue>,
+}
+
+impl Placement {
+	/// One placement per worker core of the runtime
+	///
+	/// A worker owns the queue of every port `PortBuilder::cores` set up
+	/// for its core. The workers are numbered in the order of their cores
+	pub(crate) fn all(runtime: &Runtime) -> Vec<Placement> {
+		runtime
+			.config()
+			.worker_cores()
+			.into_iter()
+			.enumerate()
+			.map(|(index, core)| Placement {
+				index,
+				core,
+				queues: runtime
+					.ports()
+					.iter()
+					.filter_map(|port| port.queues().get(&core).cloned())
+					.collect(),
+			})
+			.collect()
+	}
+
+	/// Port ids of the queues the worker owns
+	pub(crate) fn port_ids(&self) -> Vec<u16> {
+		self.queues.iter().map(PortQueue::get_portid).collect()
+	}
+
+	/// Settle the current thread as this worker
+	///
+	/// Pins the thread to the core, allocates the new `Mbuf`s from the
+	/// mempool on the core's socket and takes ownership of the queues
+	///
+	/// # Errors
+	///
+	/// If the thread can not be pinned or there is no mempool on the
+	/// socket, an error is returned
+	pub(crate) fn enter(self) -> Fallible<()> {
+		self.core.set_thread_affinity()?;
+		dpdk::attach_mempool(self.core.socket_id())?;
+		info!(
+			"worker {} on {:?} owns the queues of ports {:?}",
+			self.index,
+			self.core,
+			self.port_ids()
+		);
+		QUEUES.with(|queues| *queues.borrow_mut() = self.queues);
+		Ok(())
+	}
+}
+
+/// A running worker
+struct Handle {
+	core: CoreId,
+	stop: oneshot::Sender<Instant>, // carries the deadline to drain by
+	started: mpsc::Receiver<Fallible<()>>, // whether the worker settled on its core
+	thread: JoinHandle<Fallible<()>>,
+}
+
+/// The packet processing workers of the engine, one per worker core
+///
+/// Dropping the handle stops the workers without draining
+#[allow(missing_debug_implementations)]
+#[derive(Default)]
+pub struct Workers {
+	handles: Vec<Handle>,
+}
+
+impl Workers {
+	pub(crate) fn push(
+		&mut self,
+		core: CoreId,
+		stop: oneshot::Sender<Instant>,
+		started: mpsc::Receiver<Fallible<()>>,
+		thread: JoinHandle<Fallible<()>>,
+	) {
+		self.handles.push(Handle {
+			core,
+			stop,
+			started,
+			thread,
+		});
+	}
+
+	/// Waits for every worker to report whether it settled on its core
+	///
+	/// # Errors
+	///
+	/// If a worker could not be placed, `WorkerError::NotStarted` is
+	/// returned for the first one, `WorkerError::Panicked` if it died
+	/// before reporting. The workers are left running, dropping them
+	/// stops them
+	pub(crate) fn wait_started(&self) -> Fallible<()> {
+		for handle in &self.handles {
+			match handle.started.recv() {
+				Ok(Ok(())) => (),
+				Ok(Err(err)) => return Err(WorkerError::NotStarted(handle.core, err.to_string()).into()),
+				Err(_) => return Err(WorkerError::Panicked(handle.core).into()),
+			}
+		}
+		Ok(())
+	}
+
+	/// Returns the number of workers
+	pub fn len(&self) -> usize {
+		self.handles.len()
+	}
+
+	/// Returns whether there is no worker
+	pub fn is_empty(&self) -> bool {
+		self.handles.is_empty()
+	}
+
+	/// Stops the workers and waits for them to exit
+	///
+	/// The workers stop receiving and forward the packets in flight
+	/// until `deadline`
+	///
+	/// # Errors
+	///
+	/// If a worker panicked, `WorkerError::Panicked` is returned for the
+	/// first one. The others are still waited for
+	pub fn stop(mut self, deadline: Instant) -> Fallible<()> {
+		match self.shutdown(deadline).into_iter().next() {
+			Some(core) => Err(WorkerError::Panicked(core).into()),
+			None => Ok(()),
+		}
+	}
+
+	/// Signal every worker first so they wind down together, then join them
+	/// Returns the cores of the workers that panicked
+	fn shutdown(&mut self, deadline: Instant) -> Vec<CoreId> {
+		let handles = self.handles.drain(..).collect::<Vec<_>>();
+		let threads = handles
+			.into_iter()
+			.map(|handle| {
+				// the worker may already be gone
+				let _ = handle.stop.send(deadline);
+				(handle.core, handle.thread)
+			})
+			.collect::<Vec<_>>();
+
+		let mut panicked = vec![];
+		for (core, thread) in threads {
+			match thread.join() {
+				Ok(Ok(())) => debug!("worker on {:?} stopped", core),
+				Ok(Err(err)) => warn!("worker on {:?} failed: {}", core, err),
+				Err(_) => panicked.push(core),
+			}
+		}
+		panicked
+	}
+}
+
+impl Drop for Workers {
+	fn drop(&mut self) {
+		for core in self.shutdown(Instant::now()) {
+			warn!("worker on {:?} panicked", core);
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use failure::format_err;
+	use std::thread;
+
+	/// A worker that reports `started` and exits once told to stop
+	fn spawn(workers: &mut Workers, core: usize, started: Option<Fallible<()>>) {
+		let (stop, receiver) = oneshot::channel();
+		let (report, reports) = mpsc::channel();
+		let thread = thread::spawn(move || {
+			match started {
+				Some(started) => report.send(started).unwrap(),
+				None => panic!("died before reporting"),
+			}
+			let _ = futures::executor::block_on(receiver);
+			Ok(())
+		});
+		workers.push(CoreId::new(core), stop, reports, thread);
+	}
+
+	#[test]
+	fn started_when_every_worker_is_placed() {
+		let mut workers = Workers::default();
+		spawn(&mut workers, 1, Some(Ok(())));
+		spawn(&mut workers, 2, Some(Ok(())));
+		assert!(workers.wait_started().is_ok());
+		assert!(workers.stop(Instant::now()).is_ok());
+	}
+
+	#[test]
+	fn placement_failure_fails_startup() {
+		let mut workers = Workers::default();
+		spawn(&mut workers, 1, Some(Ok(())));
+		spawn(&mut workers, 2, Some(Err(format_err!("no mempool"))));
+		match workers.wait_started().map_err(|err| err.downcast::<WorkerError>()) {
+			Err(Ok(WorkerError::NotStarted(core, cause))) => {
+				assert_eq!(CoreId::new(2), core);
+				assert_eq!("no mempool", cause);
+			}
+			other => panic!("unexpected {:?}", other),
+		}
+
+		let mut workers = Workers::default();
+		spawn(&mut workers, 1, None);
+		match workers.wait_started().map_err(|err| err.downcast::<WorkerError>()) {
+			Err(Ok(WorkerError::Panicked(core))) => assert_eq!(CoreId::new(1), core),
+			other => panic!("unexpected {:?}", other),
+		}
+	}
+}
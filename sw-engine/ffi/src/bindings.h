@@ -121,6 +121,16 @@ int _rte_ring_dequeue(struct rte_ring *r, void **obj_p);
 unsigned int _rte_ring_enqueue_bulk(struct rte_ring *r, void *const *obj_table,
                                     unsigned int n, unsigned int *free_space);
 
+/* Enqueue several objects on a ring up to a maximum number. */
+unsigned int _rte_ring_enqueue_burst(struct rte_ring *r, void *const *obj_table,
+                                     unsigned int n, unsigned int *free_space);
+
+/* Reads the value of an mbuf's refcnt. */
+uint16_t _rte_mbuf_refcnt_read(const struct rte_mbuf *m);
+
+/* Returns non-zero if the mbuf is an indirect mbuf attached to another one. */
+int _rte_mbuf_cloned(const struct rte_mbuf *m);
+
 /* Get the Ethernet header from the packet */
 struct rte_ether_hdr *_pkt_ether_hdr(struct rte_mbuf *pkt);
 
@@ -144,6 +144,25 @@ _rte_ring_enqueue_bulk(struct rte_ring *r, void *const *obj_table,
         return rte_ring_enqueue_bulk(r, obj_table, n, free_space);
 }
 
+unsigned int
+_rte_ring_enqueue_burst(struct rte_ring *r, void *const *obj_table,
+                        unsigned int n, unsigned int *free_space)
+{
+        return rte_ring_enqueue_burst(r, obj_table, n, free_space);
+}
+
+uint16_t
+_rte_mbuf_refcnt_read(const struct rte_mbuf *m)
+{
+        return rte_mbuf_refcnt_read(m);
+}
+
+int
+_rte_mbuf_cloned(const struct rte_mbuf *m)
+{
+        return RTE_MBUF_CLONED(m) ? 1 : 0;
+}
+
 void
 stop_and_close_ports()
 {